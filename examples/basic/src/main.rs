@@ -1,12 +1,12 @@
 //! This example demonstrates how to create a simple state machine with validations and actions.
 
 use serde_json::{Map, Value};
-use stateflow::{Action, StateMachine};
+use stateflow::{Action, ActionError, Error, HandlerRegistry, StateMachine};
 
 struct Context {}
 
 #[tokio::main]
-async fn main() -> Result<(), String> {
+async fn main() -> Result<(), Error> {
     let config_content = r#"
         {
         "states": [
@@ -64,16 +64,19 @@ async fn main() -> Result<(), String> {
         action: &Action,
         _memory: &mut Map<String, Value>,
         _context: &mut Context,
-    ) {
+    ) -> Result<(), ActionError> {
         // Handle actions, possibly modifying memory
         println!("Action: {:?}", action);
+        Ok(())
     }
 
     // Create the StateMachine, ensuring the action_handler matches the expected type
     let state_machine = StateMachine::new(
         config_content,
         None,
-        |action, memory, context| Box::pin(action_handler(action, memory, context)),
+        HandlerRegistry::new().with_fallback(|action, memory, context, _payload| {
+            Box::pin(action_handler(action, memory, context))
+        }),
         memory,
         Context {},
     )?;
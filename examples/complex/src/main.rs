@@ -1,23 +1,37 @@
 //! This example demonstrates a complex state machine configuration with multiple states, transitions, and actions.
 
 use serde_json::{Map, Value};
-use stateflow::{Action, StateMachine};
+use stateflow::{Action, ActionError, Error, HandlerRegistry, StateMachine};
 
-/// An action handler that prints action details.
-async fn action_handler(action: &Action, _memory: &mut Map<String, Value>, _context: &mut Context) {
-    println!(
-        "Executing action: Type: {}, Command: {}",
-        action.action_type, action.command
+/// Handles `log` actions by printing the message.
+async fn log_handler(action: &Action) -> Result<(), ActionError> {
+    println!("[log] {}", action.command);
+    Ok(())
+}
+
+/// Handles `execute` actions by recording the last executed command in memory.
+async fn execute_handler(
+    action: &Action,
+    memory: &mut Map<String, Value>,
+) -> Result<(), ActionError> {
+    println!("[execute] {}", action.command);
+    memory.insert(
+        "last_command".to_string(),
+        Value::String(action.command.clone()),
     );
-    // Optionally modify the memory if needed
-    // For example:
-    // memory.insert("last_action".to_string(), Value::String(action.command.clone()));
+    Ok(())
+}
+
+/// Handles `alert` actions.
+async fn alert_handler(action: &Action) -> Result<(), ActionError> {
+    println!("[alert] {}", action.command);
+    Ok(())
 }
 
 struct Context {}
 
 #[tokio::main]
-async fn main() -> Result<(), String> {
+async fn main() -> Result<(), Error> {
     // JSON string representing the complex state machine configuration
     let json_config = r#"
     {
@@ -181,11 +195,24 @@ async fn main() -> Result<(), String> {
     // Initialize the memory (empty in this case)
     let memory = Map::new();
 
-    // Initialize the state machine using the configuration, memory, and the action handler
+    // Register one handler per action type used by the configuration
+    let handlers = HandlerRegistry::new()
+        .with_handler(
+            "log",
+            |action, _memory, _context: &mut Context, _payload| Box::pin(log_handler(action)),
+        )
+        .with_handler("execute", |action, memory, _context, _payload| {
+            Box::pin(execute_handler(action, memory))
+        })
+        .with_handler("alert", |action, _memory, _context, _payload| {
+            Box::pin(alert_handler(action))
+        });
+
+    // Initialize the state machine using the configuration, memory, and the action handlers
     let state_machine = StateMachine::new(
         json_config,
         Some("Idle".to_string()),
-        |action, memory, context| Box::pin(action_handler(action, memory, context)),
+        handlers,
         memory,
         Context {},
     )
@@ -209,7 +236,7 @@ async fn main() -> Result<(), String> {
 
     // This transition should fail because "Completed" state does not have a "Fail" transition
     if let Err(e) = state_machine.trigger("Fail").await {
-        println!("Expected failure: {}", e);
+        println!("Expected failure ({}): {}", e.code(), e);
     } else {
         println!("Unexpectedly succeeded in failing from a completed state");
     }
@@ -1,7 +1,7 @@
 //! This example demonstrates how to use a custom context struct with the state machine.
 //!
 use serde_json::{Map, Value};
-use stateflow::{Action, StateMachine};
+use stateflow::{Action, ActionError, HandlerRegistry, StateMachine};
 
 /// A custom context struct to be used with the state machine.
 struct MyContext {
@@ -13,7 +13,7 @@ async fn action_handler(
     action: &Action,
     _memory: &mut Map<String, Value>,
     context: &mut MyContext,
-) {
+) -> Result<(), ActionError> {
     println!(
         "Executing action: Type: {}, Command: {}",
         action.action_type, action.command
@@ -23,6 +23,7 @@ async fn action_handler(
     } else if action.action_type == "reset_counter" {
         context.counter = 0;
     }
+    Ok(())
 }
 
 #[tokio::main]
@@ -106,7 +107,9 @@ async fn main() {
     let state_machine = StateMachine::new(
         json_config,
         Some("Init".to_string()),
-        |action, memory, context| Box::pin(action_handler(action, memory, context)),
+        HandlerRegistry::new().with_fallback(|action, memory, context, _payload| {
+            Box::pin(action_handler(action, memory, context))
+        }),
         memory,
         context,
     )
@@ -0,0 +1,117 @@
+//! Built-in actions that update the memory directly, without going through a handler.
+
+use crate::path::FieldPath;
+use crate::template::Template;
+use crate::Action;
+use serde_json::{Map, Value};
+
+/// The reserved action types run by the state machine itself.
+pub(crate) const BUILTIN_ACTION_TYPES: [&str; 6] =
+    ["set", "unset", "increment", "append", "merge", "copy"];
+
+/// Returns whether the action type is a built-in action.
+pub(crate) fn is_builtin(action_type: &str) -> bool {
+    BUILTIN_ACTION_TYPES.contains(&action_type)
+}
+
+/// Checks the shape of a built-in action when the configuration is loaded. Strings are
+/// accepted wherever a template could render to the expected value.
+pub(crate) fn check(action_type: &str, field: &str, params: &Value) -> Result<(), String> {
+    if field.trim().is_empty() {
+        return Err("the command must name the memory field to update".to_string());
+    }
+    if !Template::parse(field)?.has_placeholders() {
+        FieldPath::parse(field)?.check_writable()?;
+    }
+    let valid = match action_type {
+        "increment" => matches!(params, Value::Null | Value::Number(_) | Value::String(_)),
+        "merge" => matches!(params, Value::Object(_) | Value::String(_)),
+        "copy" => matches!(params, Value::String(_)),
+        "unset" => params.is_null(),
+        _ => true,
+    };
+    if valid {
+        Ok(())
+    } else {
+        Err(format!("unexpected params {}", params))
+    }
+}
+
+/// Runs a built-in action on the memory. The command is the path of the memory field to
+/// update, and missing objects along the path are created:
+///
+/// - `set` stores `params` in the field.
+/// - `unset` removes the field.
+/// - `increment` adds `params` (1 if not set) to the numeric field, which defaults to 0.
+/// - `append` pushes `params` onto the array field, which defaults to an empty array.
+/// - `merge` merges the `params` object into the object field, which defaults to an empty
+///   object.
+/// - `copy` copies the field at the path given by `params`, in the memory or the event
+///   payload, into the field.
+pub(crate) fn execute(
+    action: &Action,
+    memory: &mut Map<String, Value>,
+    payload: &Value,
+) -> Result<(), String> {
+    let field = FieldPath::parse(&action.command)?;
+    match action.action_type.as_str() {
+        "set" => {
+            *field.entry(memory, Value::Null)? = action.params.clone();
+        }
+        "unset" => field.remove(memory)?,
+        "increment" => {
+            let step = match &action.params {
+                Value::Null => Value::from(1),
+                step => step.clone(),
+            };
+            let target = field.entry(memory, Value::from(0))?;
+            let sum = match (target.as_i64(), step.as_i64()) {
+                (Some(current), Some(step)) => current
+                    .checked_add(step)
+                    .map(Value::from)
+                    .ok_or_else(|| format!("incrementing '{}' overflows", field))?,
+                _ => match (target.as_f64(), step.as_f64()) {
+                    (Some(current), Some(step)) => Value::from(current + step),
+                    _ => {
+                        return Err(format!(
+                            "cannot increment {} by {} in field '{}'",
+                            target, step, field
+                        ))
+                    }
+                },
+            };
+            *target = sum;
+        }
+        "append" => match field.entry(memory, Value::Array(Vec::new()))? {
+            Value::Array(items) => items.push(action.params.clone()),
+            _ => return Err(format!("field '{}' is not an array", field)),
+        },
+        "merge" => {
+            let Value::Object(params) = &action.params else {
+                return Err(format!(
+                    "params of 'merge' must be an object, got {}",
+                    action.params
+                ));
+            };
+            match field.entry(memory, Value::Object(Map::new()))? {
+                Value::Object(fields) => {
+                    fields.extend(params.iter().map(|(k, v)| (k.clone(), v.clone())));
+                }
+                _ => return Err(format!("field '{}' is not an object", field)),
+            }
+        }
+        "copy" => {
+            let source = action
+                .params
+                .as_str()
+                .ok_or_else(|| "params of 'copy' must name the source field".to_string())?;
+            let value = FieldPath::parse(source)?
+                .get(memory, payload)?
+                .cloned()
+                .ok_or_else(|| format!("source field '{}' is missing", source))?;
+            *field.entry(memory, Value::Null)? = value;
+        }
+        action_type => return Err(format!("'{}' is not a built-in action", action_type)),
+    }
+    Ok(())
+}
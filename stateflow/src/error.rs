@@ -0,0 +1,235 @@
+//! Error types returned by the state machine.
+
+use std::fmt::{self, Display, Formatter};
+
+/// Details about a validation rule that was not satisfied.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Violation {
+    /// The memory field the rule was applied to, as written in the configuration.
+    pub field: String,
+    /// The location of the value that failed, with wildcards replaced by array indexes.
+    pub path: String,
+    /// The rule type that failed (e.g. `min_value`, `type_check`).
+    pub rule: String,
+    /// A human readable description of the failure.
+    pub message: String,
+}
+
+impl Display for Violation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "Field '{}' {}", self.path, self.message)
+    }
+}
+
+/// An error reported by an action handler.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ActionError {
+    message: String,
+}
+
+impl ActionError {
+    /// Creates a new action error with the given message.
+    pub fn new(message: impl Into<String>) -> Self {
+        ActionError {
+            message: message.into(),
+        }
+    }
+
+    /// Returns the error message.
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl Display for ActionError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for ActionError {}
+
+impl From<String> for ActionError {
+    fn from(message: String) -> Self {
+        ActionError::new(message)
+    }
+}
+
+impl From<&str> for ActionError {
+    fn from(message: &str) -> Self {
+        ActionError::new(message)
+    }
+}
+
+/// Errors produced while loading a configuration or driving the state machine.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Error {
+    /// The configuration is not valid JSON or could not be deserialized.
+    ConfigParse(String),
+    /// The configuration does not conform to the configuration JSON schema.
+    SchemaViolation(String),
+    /// The configuration is well-formed but semantically invalid.
+    InvalidConfig(String),
+    /// A state referenced by the configuration or the caller does not exist.
+    UnknownState(String),
+    /// The current state has no transition for the triggered event.
+    NoTransition {
+        /// The event that was triggered.
+        event: String,
+        /// The state the machine was in.
+        state: String,
+    },
+    /// The event payload does not conform to the event's payload schema.
+    InvalidPayload {
+        /// The event that was triggered.
+        event: String,
+        /// A human readable description of the failure.
+        message: String,
+    },
+    /// A state or transition validation rule was not satisfied.
+    Validation(Violation),
+    /// Several validation rules were not satisfied, reported together in
+    /// `ValidationMode::CollectAll`.
+    Validations(Vec<Violation>),
+    /// A condition could not be evaluated.
+    Condition {
+        /// The memory field the condition refers to.
+        field: String,
+        /// A human readable description of the failure.
+        message: String,
+    },
+    /// An action handler reported a failure.
+    ActionFailed {
+        /// The type of the action that failed.
+        action_type: String,
+        /// A human readable description of the failure.
+        message: String,
+    },
+    /// An action's command or parameters could not be rendered, or the rendered parameters do
+    /// not match the schema of the action's type.
+    Template {
+        /// The type of the action.
+        action_type: String,
+        /// A human readable description of the failure.
+        message: String,
+    },
+    /// Eventless transitions kept firing past the configured limit.
+    EventlessLoop {
+        /// The active states when the limit was reached.
+        state: String,
+        /// The configured maximum number of eventless steps.
+        limit: usize,
+    },
+    /// The configuration uses an action type that no registered handler covers.
+    MissingHandler(String),
+    /// A handler is registered for a built-in action type, which never reaches a handler.
+    BuiltinHandler(String),
+    /// A snapshot does not fit the configuration it is restored with.
+    SnapshotMismatch(String),
+    /// The state store failed to load or save a snapshot.
+    Store(String),
+    /// Another writer advanced the instance in the state store first.
+    VersionConflict {
+        /// The id of the instance in the state store.
+        instance_id: String,
+        /// The version the step started from.
+        expected_version: u64,
+    },
+}
+
+impl Error {
+    /// Returns a stable, machine-readable code identifying the kind of error.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::ConfigParse(_) => "config_parse",
+            Error::SchemaViolation(_) => "schema_violation",
+            Error::InvalidConfig(_) => "invalid_config",
+            Error::UnknownState(_) => "unknown_state",
+            Error::NoTransition { .. } => "no_transition",
+            Error::InvalidPayload { .. } => "invalid_payload",
+            Error::Validation(_) | Error::Validations(_) => "validation_failed",
+            Error::Condition { .. } => "condition_failed",
+            Error::ActionFailed { .. } => "action_failed",
+            Error::Template { .. } => "template_failed",
+            Error::EventlessLoop { .. } => "eventless_loop",
+            Error::MissingHandler(_) => "missing_handler",
+            Error::BuiltinHandler(_) => "builtin_handler",
+            Error::SnapshotMismatch(_) => "snapshot_mismatch",
+            Error::Store(_) => "store_failed",
+            Error::VersionConflict { .. } => "version_conflict",
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::ConfigParse(message) => write!(f, "Invalid configuration: {}", message),
+            Error::SchemaViolation(message) => write!(
+                f,
+                "JSON configuration does not conform to schema: {}",
+                message
+            ),
+            Error::InvalidConfig(message) => write!(f, "{}", message),
+            Error::UnknownState(state) => {
+                write!(f, "State '{}' not found in state machine.", state)
+            }
+            Error::NoTransition { event, state } => write!(
+                f,
+                "No transition found for event '{}' from state '{}'.",
+                event, state
+            ),
+            Error::InvalidPayload { event, message } => {
+                write!(f, "Invalid payload for event '{}': {}", event, message)
+            }
+            Error::Validation(violation) => write!(f, "Validation failed: {}", violation),
+            Error::Validations(violations) => {
+                let violations: Vec<String> = violations.iter().map(ToString::to_string).collect();
+                write!(f, "Validation failed: {}", violations.join("; "))
+            }
+            Error::Condition { field, message } => write!(
+                f,
+                "Condition evaluation failed on field '{}': {}",
+                field, message
+            ),
+            Error::ActionFailed {
+                action_type,
+                message,
+            } => write!(f, "Action '{}' failed: {}", action_type, message),
+            Error::Template {
+                action_type,
+                message,
+            } => write!(f, "Failed to render action '{}': {}", action_type, message),
+            Error::EventlessLoop { state, limit } => write!(
+                f,
+                "Eventless transitions did not settle after {} steps (stuck in '{}').",
+                limit, state
+            ),
+            Error::MissingHandler(action_type) => write!(
+                f,
+                "No handler registered for action type '{}' and no fallback handler.",
+                action_type
+            ),
+            Error::BuiltinHandler(action_type) => write!(
+                f,
+                "A handler is registered for the built-in action type '{}', which is reserved.",
+                action_type
+            ),
+            Error::SnapshotMismatch(message) => {
+                write!(f, "Snapshot does not match the configuration: {}", message)
+            }
+            Error::Store(message) => write!(f, "State store failed: {}", message),
+            Error::VersionConflict {
+                instance_id,
+                expected_version,
+            } => write!(
+                f,
+                "Instance '{}' was modified concurrently: expected version {}.",
+                instance_id, expected_version
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
@@ -0,0 +1,32 @@
+//! Notifications published to subscribers as the state machine moves.
+
+use crate::{Error, JournalTrigger};
+
+/// A step taken, or an event rejected, by a state machine. See
+/// [`crate::StateMachine::subscribe`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct TransitionEvent {
+    /// What caused the step: an event with its payload, a timer or a round of eventless
+    /// transitions.
+    pub trigger: JournalTrigger,
+    /// The active leaf states before the step.
+    pub from: Vec<String>,
+    /// The active leaf states after the step. Equal to `from` when the event was rejected.
+    pub to: Vec<String>,
+    /// When the step was taken, in milliseconds since the Unix epoch.
+    pub timestamp_ms: u64,
+    /// `Ok` if the step completed, or the error it failed with. A failed step either left the
+    /// machine where it was or, under the `redirect` error policy, moved it to the `on_error`
+    /// state.
+    pub outcome: Result<(), Error>,
+}
+
+impl TransitionEvent {
+    /// Returns the event name, if the step was caused by an event.
+    pub fn event(&self) -> Option<&str> {
+        match &self.trigger {
+            JournalTrigger::Event { name, .. } => Some(name),
+            _ => None,
+        }
+    }
+}
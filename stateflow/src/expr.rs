@@ -0,0 +1,1031 @@
+//! A small, side-effect free expression language used by transition guards and `expr`
+//! validation rules, e.g. `total == sum(items[*].price * items[*].qty) && status != 'closed'`.
+//!
+//! Expressions read fields of the memory (or of the event payload, with the `event` prefix)
+//! through field paths, and `$value` is the value of the field an `expr` rule is applied to.
+//! They support arithmetic (`+ - * / %`, where `+` also joins strings), comparisons
+//! (`== != < <= > >=`), boolean logic (`&&`/`and`, `||`/`or`, `!`/`not`), membership (`in`),
+//! list literals and a fixed set of functions. Arithmetic and ordering operators apply
+//! element-wise to the lists produced by wildcard paths.
+//!
+//! Expressions are parsed and type-checked when the configuration is loaded. They cannot
+//! loop, call out or change anything, and their nesting depth is limited.
+
+use crate::datetime;
+use crate::path::FieldPath;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::cmp::Ordering;
+use std::fmt::{self, Display, Formatter};
+
+/// The maximum nesting depth of an expression.
+const MAX_DEPTH: usize = 64;
+
+/// A lexical token.
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(f64),
+    Str(String),
+    /// A field path, a keyword or a function name.
+    Name(String),
+    /// The value of the field a rule is applied to.
+    Value,
+    Operator(&'static str),
+    Open,
+    Close,
+    OpenList,
+    CloseList,
+    Comma,
+}
+
+impl Display for Token {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Number(number) => write!(f, "'{}'", number),
+            Token::Str(string) => write!(f, "string '{}'", string),
+            Token::Name(name) => write!(f, "'{}'", name),
+            Token::Value => write!(f, "'$value'"),
+            Token::Operator(operator) => write!(f, "'{}'", operator),
+            Token::Open => write!(f, "'('"),
+            Token::Close => write!(f, "')'"),
+            Token::OpenList => write!(f, "'['"),
+            Token::CloseList => write!(f, "']'"),
+            Token::Comma => write!(f, "','"),
+        }
+    }
+}
+
+/// The operators, longest first so that `<=` is not read as `<`.
+const OPERATORS: [&str; 17] = [
+    "==", "!=", "<=", ">=", "&&", "||", "<", ">", "+", "-", "*", "/", "%", "!", "(", ")", ",",
+];
+
+/// A binary operator.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    And,
+    Or,
+    In,
+}
+
+impl BinaryOp {
+    /// Returns the operator as written in expressions.
+    fn symbol(self) -> &'static str {
+        match self {
+            BinaryOp::Add => "+",
+            BinaryOp::Sub => "-",
+            BinaryOp::Mul => "*",
+            BinaryOp::Div => "/",
+            BinaryOp::Rem => "%",
+            BinaryOp::Eq => "==",
+            BinaryOp::Ne => "!=",
+            BinaryOp::Lt => "<",
+            BinaryOp::Le => "<=",
+            BinaryOp::Gt => ">",
+            BinaryOp::Ge => ">=",
+            BinaryOp::And => "&&",
+            BinaryOp::Or => "||",
+            BinaryOp::In => "in",
+        }
+    }
+}
+
+/// A built-in function.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Function {
+    Sum,
+    Avg,
+    Min,
+    Max,
+    Count,
+    Len,
+    All,
+    Any,
+    Lower,
+    Upper,
+    Trim,
+    Contains,
+    StartsWith,
+    EndsWith,
+    Abs,
+    Round,
+    Floor,
+    Ceil,
+}
+
+/// The functions by name.
+const FUNCTIONS: [(&str, Function); 18] = [
+    ("sum", Function::Sum),
+    ("avg", Function::Avg),
+    ("min", Function::Min),
+    ("max", Function::Max),
+    ("count", Function::Count),
+    ("len", Function::Len),
+    ("all", Function::All),
+    ("any", Function::Any),
+    ("lower", Function::Lower),
+    ("upper", Function::Upper),
+    ("trim", Function::Trim),
+    ("contains", Function::Contains),
+    ("starts_with", Function::StartsWith),
+    ("ends_with", Function::EndsWith),
+    ("abs", Function::Abs),
+    ("round", Function::Round),
+    ("floor", Function::Floor),
+    ("ceil", Function::Ceil),
+];
+
+impl Function {
+    /// Looks up a function by name.
+    fn from_name(name: &str) -> Option<Self> {
+        FUNCTIONS
+            .iter()
+            .find(|(candidate, _)| *candidate == name)
+            .map(|(_, function)| *function)
+    }
+
+    /// Returns the name of the function.
+    fn name(self) -> &'static str {
+        FUNCTIONS
+            .iter()
+            .find(|(_, function)| *function == self)
+            .map_or("", |(name, _)| name)
+    }
+}
+
+/// A node of a parsed expression.
+#[derive(Debug, Clone)]
+enum Expr {
+    Literal(Value),
+    Field(FieldPath),
+    Value,
+    List(Vec<Expr>),
+    Not(Box<Expr>),
+    Neg(Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+    Call(Function, Vec<Expr>),
+}
+
+/// The static type of an expression. Fields are only known at run time.
+#[derive(Debug, Clone, PartialEq)]
+enum Type {
+    Any,
+    Null,
+    Bool,
+    Number,
+    String,
+    List(Box<Type>),
+}
+
+impl Type {
+    /// Returns whether a value of this type may be a number.
+    fn number(&self) -> bool {
+        matches!(self, Type::Number | Type::Any)
+    }
+
+    /// Returns whether a value of this type may be a string.
+    fn string(&self) -> bool {
+        matches!(self, Type::String | Type::Any)
+    }
+
+    /// Returns whether a value of this type may be a boolean.
+    fn boolean(&self) -> bool {
+        matches!(self, Type::Bool | Type::Any)
+    }
+
+    /// Returns whether a value of this type may be a list whose items satisfy `item`.
+    fn list_of(&self, item: impl Fn(&Type) -> bool) -> bool {
+        match self {
+            Type::List(inner) => item(inner),
+            Type::Any => true,
+            _ => false,
+        }
+    }
+}
+
+impl Display for Type {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Type::Any => write!(f, "any"),
+            Type::Null => write!(f, "null"),
+            Type::Bool => write!(f, "boolean"),
+            Type::Number => write!(f, "number"),
+            Type::String => write!(f, "string"),
+            Type::List(item) => write!(f, "list of {}", item),
+        }
+    }
+}
+
+/// A parsed and type-checked expression.
+#[derive(Debug, Clone)]
+pub(crate) struct Expression {
+    text: String,
+    root: Expr,
+}
+
+impl Expression {
+    /// Parses and type-checks a guard expression, which must yield a boolean.
+    pub(crate) fn parse(text: &str) -> Result<Self, String> {
+        Self::parse_with(text, false)
+    }
+
+    /// Parses and type-checks a rule expression, which may use `$value` and must yield a
+    /// boolean.
+    pub(crate) fn parse_rule(text: &str) -> Result<Self, String> {
+        Self::parse_with(text, true)
+    }
+
+    fn parse_with(text: &str, allow_value: bool) -> Result<Self, String> {
+        let error = |message: String| format!("invalid expression '{}': {}", text, message);
+        let tokens = tokenize(text).map_err(error)?;
+        let mut parser = Parser {
+            tokens,
+            position: 0,
+            depth: 0,
+        };
+        let root = parser.parse_or().map_err(error)?;
+        if let Some(token) = parser.tokens.get(parser.position) {
+            return Err(error(format!("unexpected {}", token)));
+        }
+        let result = type_of(&root, allow_value).map_err(error)?;
+        if !result.boolean() {
+            return Err(error(format!("expected a boolean, got a {}", result)));
+        }
+        Ok(Expression {
+            text: text.to_string(),
+            root,
+        })
+    }
+
+    /// Evaluates the expression. `value` is the value of the field a rule is applied to.
+    pub(crate) fn evaluate(
+        &self,
+        memory: &Map<String, Value>,
+        payload: &Value,
+        value: Option<&Value>,
+    ) -> Result<bool, String> {
+        let scope = Scope {
+            memory,
+            payload,
+            value,
+        };
+        match evaluate(&self.root, &scope)? {
+            Value::Bool(result) => Ok(result),
+            other => Err(format!(
+                "expression '{}' yielded {} instead of a boolean",
+                self.text, other
+            )),
+        }
+    }
+}
+
+impl Display for Expression {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.text)
+    }
+}
+
+impl Serialize for Expression {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.text.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Expression {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        Expression::parse(&text).map_err(serde::de::Error::custom)
+    }
+}
+
+/// An expression of an `expr` validation rule, which may refer to the field's value as
+/// `$value`.
+#[derive(Debug, Clone, Serialize)]
+#[serde(transparent)]
+pub(crate) struct RuleExpression(pub(crate) Expression);
+
+impl<'de> Deserialize<'de> for RuleExpression {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        Expression::parse_rule(&text)
+            .map(RuleExpression)
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+/// Splits an expression into tokens.
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        if c.is_whitespace() {
+            rest = &rest[c.len_utf8()..];
+        } else if c.is_ascii_digit() {
+            let end = rest
+                .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+                .unwrap_or(rest.len());
+            let number = rest[..end]
+                .parse()
+                .map_err(|e| format!("invalid number '{}': {}", &rest[..end], e))?;
+            tokens.push(Token::Number(number));
+            rest = &rest[end..];
+        } else if c == '\'' || c == '"' {
+            let (string, length) = read_string(rest, c)?;
+            tokens.push(Token::Str(string));
+            rest = &rest[length..];
+        } else if c == '[' {
+            tokens.push(Token::OpenList);
+            rest = &rest[1..];
+        } else if c == ']' {
+            tokens.push(Token::CloseList);
+            rest = &rest[1..];
+        } else if let Some(name) = rest.strip_prefix("$value") {
+            if name.starts_with(is_name_char) {
+                return Err(format!("unknown variable '{}'", read_name(rest)));
+            }
+            tokens.push(Token::Value);
+            rest = name;
+        } else if c.is_alphabetic() || c == '_' {
+            let name = read_name(rest);
+            tokens.push(Token::Name(name.to_string()));
+            rest = &rest[name.len()..];
+        } else if let Some(operator) = OPERATORS.iter().find(|op| rest.starts_with(**op)) {
+            tokens.push(match *operator {
+                "(" => Token::Open,
+                ")" => Token::Close,
+                "," => Token::Comma,
+                operator => Token::Operator(operator),
+            });
+            rest = &rest[operator.len()..];
+        } else {
+            return Err(format!("unexpected character '{}'", c));
+        }
+    }
+    Ok(tokens)
+}
+
+/// Returns whether a character may continue a name.
+fn is_name_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// Reads a name or field path: identifiers joined by dots, each optionally followed by
+/// bracketed indexes or `[*]`.
+fn read_name(text: &str) -> &str {
+    let mut end = 0;
+    loop {
+        end += text[end..]
+            .find(|c: char| !is_name_char(c) && c != '$')
+            .unwrap_or(text.len() - end);
+        let rest = &text[end..];
+        if let Some(index) = rest.strip_prefix('[') {
+            let length = index.find(|c: char| !c.is_ascii_digit() && c != '*');
+            match length {
+                Some(length) if length > 0 && index[length..].starts_with(']') => {
+                    end += length + 2;
+                    continue;
+                }
+                _ => return &text[..end],
+            }
+        }
+        if rest.starts_with('.') && rest[1..].starts_with(is_name_char) {
+            end += 1;
+            continue;
+        }
+        return &text[..end];
+    }
+}
+
+/// Reads a quoted string, returning its content and its length in the text.
+fn read_string(text: &str, quote: char) -> Result<(String, usize), String> {
+    let mut string = String::new();
+    let mut chars = text.char_indices().skip(1);
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some((_, 'n')) => string.push('\n'),
+                Some((_, 't')) => string.push('\t'),
+                Some((_, escaped)) => string.push(escaped),
+                None => break,
+            },
+            c if c == quote => return Ok((string, i + 1)),
+            c => string.push(c),
+        }
+    }
+    Err("unterminated string".to_string())
+}
+
+/// A recursive descent parser over the tokens of an expression.
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    /// Consumes the next token if it is the given operator or keyword.
+    fn accept(&mut self, operator: &str, keyword: &str) -> bool {
+        let found = match self.peek() {
+            Some(Token::Operator(op)) => *op == operator,
+            Some(Token::Name(name)) => name == keyword,
+            _ => false,
+        };
+        if found {
+            self.position += 1;
+        }
+        found
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<(), String> {
+        match self.next() {
+            Some(token) if token == expected => Ok(()),
+            Some(token) => Err(format!("expected {}, found {}", expected, token)),
+            None => Err(format!("expected {} at the end", expected)),
+        }
+    }
+
+    /// Adds a level of nesting, failing past the maximum depth.
+    fn deepen(&mut self) -> Result<(), String> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            return Err("expression is nested too deeply".to_string());
+        }
+        Ok(())
+    }
+
+    fn nested<T>(
+        &mut self,
+        parse: impl FnOnce(&mut Self) -> Result<T, String>,
+    ) -> Result<T, String> {
+        self.deepen()?;
+        let result = parse(self);
+        self.depth -= 1;
+        result
+    }
+
+    // Binary operators are left-associative, so every operator of a chain nests the operands
+    // before it one level deeper and counts toward the depth until the chain ends
+
+    fn parse_or(&mut self) -> Result<Expr, String> {
+        let depth = self.depth;
+        let mut left = self.parse_and()?;
+        while self.accept("||", "or") {
+            self.deepen()?;
+            let right = self.parse_and()?;
+            left = Expr::Binary(BinaryOp::Or, Box::new(left), Box::new(right));
+        }
+        self.depth = depth;
+        Ok(left)
+    }
+
+    fn parse_and(&mut self) -> Result<Expr, String> {
+        let depth = self.depth;
+        let mut left = self.parse_not()?;
+        while self.accept("&&", "and") {
+            self.deepen()?;
+            let right = self.parse_not()?;
+            left = Expr::Binary(BinaryOp::And, Box::new(left), Box::new(right));
+        }
+        self.depth = depth;
+        Ok(left)
+    }
+
+    fn parse_not(&mut self) -> Result<Expr, String> {
+        if self.accept("!", "not") {
+            return self.nested(|parser| Ok(Expr::Not(Box::new(parser.parse_not()?))));
+        }
+        self.parse_comparison()
+    }
+
+    fn parse_comparison(&mut self) -> Result<Expr, String> {
+        let left = self.parse_additive()?;
+        let operator = match self.peek() {
+            Some(Token::Operator("==")) => BinaryOp::Eq,
+            Some(Token::Operator("!=")) => BinaryOp::Ne,
+            Some(Token::Operator("<")) => BinaryOp::Lt,
+            Some(Token::Operator("<=")) => BinaryOp::Le,
+            Some(Token::Operator(">")) => BinaryOp::Gt,
+            Some(Token::Operator(">=")) => BinaryOp::Ge,
+            Some(Token::Name(name)) if name == "in" => BinaryOp::In,
+            _ => return Ok(left),
+        };
+        self.position += 1;
+        let right = self.parse_additive()?;
+        Ok(Expr::Binary(operator, Box::new(left), Box::new(right)))
+    }
+
+    fn parse_additive(&mut self) -> Result<Expr, String> {
+        let depth = self.depth;
+        let mut left = self.parse_multiplicative()?;
+        loop {
+            let operator = match self.peek() {
+                Some(Token::Operator("+")) => BinaryOp::Add,
+                Some(Token::Operator("-")) => BinaryOp::Sub,
+                _ => break,
+            };
+            self.position += 1;
+            self.deepen()?;
+            let right = self.parse_multiplicative()?;
+            left = Expr::Binary(operator, Box::new(left), Box::new(right));
+        }
+        self.depth = depth;
+        Ok(left)
+    }
+
+    fn parse_multiplicative(&mut self) -> Result<Expr, String> {
+        let depth = self.depth;
+        let mut left = self.parse_unary()?;
+        loop {
+            let operator = match self.peek() {
+                Some(Token::Operator("*")) => BinaryOp::Mul,
+                Some(Token::Operator("/")) => BinaryOp::Div,
+                Some(Token::Operator("%")) => BinaryOp::Rem,
+                _ => break,
+            };
+            self.position += 1;
+            self.deepen()?;
+            let right = self.parse_unary()?;
+            left = Expr::Binary(operator, Box::new(left), Box::new(right));
+        }
+        self.depth = depth;
+        Ok(left)
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, String> {
+        if self.accept("-", "") {
+            return self.nested(|parser| Ok(Expr::Neg(Box::new(parser.parse_unary()?))));
+        }
+        self.nested(Self::parse_primary)
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr, String> {
+        match self.next() {
+            Some(Token::Number(number)) => Ok(Expr::Literal(number_value(number)?)),
+            Some(Token::Str(string)) => Ok(Expr::Literal(Value::String(string))),
+            Some(Token::Value) => Ok(Expr::Value),
+            Some(Token::Open) => {
+                let inner = self.parse_or()?;
+                self.expect(Token::Close)?;
+                Ok(inner)
+            }
+            Some(Token::OpenList) => {
+                let items = self.parse_arguments(Token::CloseList)?;
+                Ok(Expr::List(items))
+            }
+            Some(Token::Name(name)) => match name.as_str() {
+                "true" => Ok(Expr::Literal(Value::Bool(true))),
+                "false" => Ok(Expr::Literal(Value::Bool(false))),
+                "null" => Ok(Expr::Literal(Value::Null)),
+                "and" | "or" | "not" | "in" => Err(format!("unexpected '{}'", name)),
+                _ if self.peek() == Some(&Token::Open) => {
+                    let function = Function::from_name(&name)
+                        .ok_or_else(|| format!("unknown function '{}'", name))?;
+                    self.position += 1;
+                    let arguments = self.parse_arguments(Token::Close)?;
+                    Ok(Expr::Call(function, arguments))
+                }
+                _ => Ok(Expr::Field(FieldPath::parse(&name)?)),
+            },
+            Some(token) => Err(format!("unexpected {}", token)),
+            None => Err("unexpected end of expression".to_string()),
+        }
+    }
+
+    /// Parses comma-separated expressions up to the closing token.
+    fn parse_arguments(&mut self, close: Token) -> Result<Vec<Expr>, String> {
+        let mut arguments = Vec::new();
+        if self.peek() == Some(&close) {
+            self.position += 1;
+            return Ok(arguments);
+        }
+        loop {
+            arguments.push(self.parse_or()?);
+            match self.next() {
+                Some(Token::Comma) => {}
+                Some(token) if token == close => return Ok(arguments),
+                Some(token) => return Err(format!("unexpected {}", token)),
+                None => return Err(format!("expected {} at the end", close)),
+            }
+        }
+    }
+}
+
+/// Type-checks an expression and returns its type.
+fn type_of(expr: &Expr, allow_value: bool) -> Result<Type, String> {
+    let check = |expr| type_of(expr, allow_value);
+    match expr {
+        Expr::Literal(value) => Ok(match value {
+            Value::Null => Type::Null,
+            Value::Bool(_) => Type::Bool,
+            Value::Number(_) => Type::Number,
+            Value::String(_) => Type::String,
+            _ => Type::Any,
+        }),
+        Expr::Field(path) if path.has_wildcard() => Ok(Type::List(Box::new(Type::Any))),
+        Expr::Field(_) => Ok(Type::Any),
+        Expr::Value if allow_value => Ok(Type::Any),
+        Expr::Value => Err("'$value' can only be used in validation rules".to_string()),
+        Expr::List(items) => {
+            let mut item_type: Option<Type> = None;
+            for item in items {
+                let ty = check(item)?;
+                item_type = Some(match item_type {
+                    Some(previous) if previous != ty => Type::Any,
+                    _ => ty,
+                });
+            }
+            Ok(Type::List(Box::new(item_type.unwrap_or(Type::Any))))
+        }
+        Expr::Not(inner) => match check(inner)? {
+            ty if ty.boolean() => Ok(Type::Bool),
+            ty => Err(format!("'!' cannot be applied to a {}", ty)),
+        },
+        Expr::Neg(inner) => match check(inner)? {
+            ty if ty.number() || ty.list_of(Type::number) => Ok(ty),
+            ty => Err(format!("'-' cannot be applied to a {}", ty)),
+        },
+        Expr::Binary(op, left, right) => binary_type(*op, check(left)?, check(right)?),
+        Expr::Call(function, arguments) => {
+            let types = arguments.iter().map(check).collect::<Result<Vec<_>, _>>()?;
+            call_type(*function, &types)
+        }
+    }
+}
+
+/// Returns the type of a binary operation.
+fn binary_type(op: BinaryOp, left: Type, right: Type) -> Result<Type, String> {
+    let mismatch = |left: &Type, right: &Type| {
+        format!(
+            "'{}' cannot be applied to a {} and a {}",
+            op.symbol(),
+            left,
+            right
+        )
+    };
+    match op {
+        BinaryOp::Eq | BinaryOp::Ne => Ok(Type::Bool),
+        BinaryOp::And | BinaryOp::Or if left.boolean() && right.boolean() => Ok(Type::Bool),
+        BinaryOp::In if right.string() || right.list_of(|_| true) => Ok(Type::Bool),
+        BinaryOp::And | BinaryOp::Or | BinaryOp::In => Err(mismatch(&left, &right)),
+        _ => {
+            // Arithmetic and ordering apply element-wise to lists
+            let (left_item, right_item, list) = match (&left, &right) {
+                (Type::List(l), Type::List(r)) => ((**l).clone(), (**r).clone(), true),
+                (Type::List(l), r) => ((**l).clone(), r.clone(), true),
+                (l, Type::List(r)) => (l.clone(), (**r).clone(), true),
+                (l, r) => (l.clone(), r.clone(), false),
+            };
+            let numbers = left_item.number() && right_item.number();
+            let strings = left_item.string() && right_item.string();
+            let item = match op {
+                BinaryOp::Lt | BinaryOp::Le | BinaryOp::Gt | BinaryOp::Ge if numbers || strings => {
+                    Type::Bool
+                }
+                BinaryOp::Add if left_item == Type::String && right_item == Type::String => {
+                    Type::String
+                }
+                // `+` adds numbers or joins strings, which is only known at run time
+                BinaryOp::Add
+                    if (left_item == Type::Any || right_item == Type::Any)
+                        && (numbers || strings) =>
+                {
+                    Type::Any
+                }
+                BinaryOp::Add | BinaryOp::Sub | BinaryOp::Mul | BinaryOp::Div | BinaryOp::Rem
+                    if numbers =>
+                {
+                    Type::Number
+                }
+                _ => return Err(mismatch(&left, &right)),
+            };
+            Ok(if list {
+                Type::List(Box::new(item))
+            } else {
+                item
+            })
+        }
+    }
+}
+
+/// Returns the type of a function call.
+fn call_type(function: Function, arguments: &[Type]) -> Result<Type, String> {
+    let invalid = || {
+        let types: Vec<String> = arguments.iter().map(ToString::to_string).collect();
+        format!(
+            "invalid arguments for {}: ({})",
+            function.name(),
+            types.join(", ")
+        )
+    };
+    let ok = |valid: bool, result: Type| if valid { Ok(result) } else { Err(invalid()) };
+    match (function, arguments) {
+        (Function::Sum | Function::Avg | Function::Min | Function::Max, [list]) => {
+            ok(list.list_of(Type::number), Type::Number)
+        }
+        (Function::Min | Function::Max, numbers) => ok(
+            numbers.len() > 1 && numbers.iter().all(Type::number),
+            Type::Number,
+        ),
+        (Function::Count, [list]) => ok(list.list_of(|_| true), Type::Number),
+        (Function::Len, [value]) => ok(value.string() || value.list_of(|_| true), Type::Number),
+        (Function::All | Function::Any, [list]) => ok(list.list_of(Type::boolean), Type::Bool),
+        (Function::Lower | Function::Upper | Function::Trim, [text]) => {
+            ok(text.string(), Type::String)
+        }
+        (Function::Contains, [haystack, _]) => {
+            ok(haystack.string() || haystack.list_of(|_| true), Type::Bool)
+        }
+        (Function::StartsWith | Function::EndsWith, [text, affix]) => {
+            ok(text.string() && affix.string(), Type::Bool)
+        }
+        (Function::Abs | Function::Round | Function::Floor | Function::Ceil, [number]) => {
+            ok(number.number(), Type::Number)
+        }
+        _ => Err(invalid()),
+    }
+}
+
+/// The values an expression is evaluated against.
+struct Scope<'v> {
+    memory: &'v Map<String, Value>,
+    payload: &'v Value,
+    value: Option<&'v Value>,
+}
+
+/// Evaluates an expression node.
+fn evaluate(expr: &Expr, scope: &Scope<'_>) -> Result<Value, String> {
+    match expr {
+        Expr::Literal(value) => Ok(value.clone()),
+        Expr::Field(path) if path.has_wildcard() => Ok(Value::Array(
+            path.resolve(scope.memory, scope.payload)
+                .into_iter()
+                .map(|(_, value)| value.cloned().unwrap_or(Value::Null))
+                .collect(),
+        )),
+        Expr::Field(path) => Ok(path
+            .get(scope.memory, scope.payload)?
+            .cloned()
+            .unwrap_or(Value::Null)),
+        Expr::Value => Ok(scope.value.cloned().unwrap_or(Value::Null)),
+        Expr::List(items) => items
+            .iter()
+            .map(|item| evaluate(item, scope))
+            .collect::<Result<_, _>>()
+            .map(Value::Array),
+        Expr::Not(inner) => Ok(Value::Bool(!boolean(&evaluate(inner, scope)?)?)),
+        Expr::Neg(inner) => element_wise(&evaluate(inner, scope)?, &Value::Null, |value, _| {
+            number_value(-number(value, "-")?)
+        }),
+        Expr::Binary(BinaryOp::And, left, right) => Ok(Value::Bool(
+            boolean(&evaluate(left, scope)?)? && boolean(&evaluate(right, scope)?)?,
+        )),
+        Expr::Binary(BinaryOp::Or, left, right) => Ok(Value::Bool(
+            boolean(&evaluate(left, scope)?)? || boolean(&evaluate(right, scope)?)?,
+        )),
+        Expr::Binary(op, left, right) => {
+            binary(*op, &evaluate(left, scope)?, &evaluate(right, scope)?)
+        }
+        Expr::Call(function, arguments) => {
+            let arguments = arguments
+                .iter()
+                .map(|argument| evaluate(argument, scope))
+                .collect::<Result<Vec<_>, _>>()?;
+            call(*function, &arguments)
+        }
+    }
+}
+
+/// Applies a binary operator other than `&&` and `||`.
+fn binary(op: BinaryOp, left: &Value, right: &Value) -> Result<Value, String> {
+    match op {
+        BinaryOp::Eq => Ok(Value::Bool(equals(left, right))),
+        BinaryOp::Ne => Ok(Value::Bool(!equals(left, right))),
+        BinaryOp::In => match right {
+            Value::Array(items) => Ok(Value::Bool(items.iter().any(|item| equals(item, left)))),
+            Value::String(text) => Ok(Value::Bool(text.contains(string(left, "in")?))),
+            _ => Err(format!("'in' cannot be applied to {}", right)),
+        },
+        _ => element_wise(left, right, |left, right| {
+            let symbol = op.symbol();
+            match op {
+                BinaryOp::Lt => Ok(Value::Bool(order(left, right, symbol)?.is_lt())),
+                BinaryOp::Le => Ok(Value::Bool(order(left, right, symbol)?.is_le())),
+                BinaryOp::Gt => Ok(Value::Bool(order(left, right, symbol)?.is_gt())),
+                BinaryOp::Ge => Ok(Value::Bool(order(left, right, symbol)?.is_ge())),
+                BinaryOp::Add => match (left, right) {
+                    (Value::String(left), Value::String(right)) => {
+                        Ok(Value::String(format!("{}{}", left, right)))
+                    }
+                    _ => number_value(number(left, symbol)? + number(right, symbol)?),
+                },
+                BinaryOp::Sub => number_value(number(left, symbol)? - number(right, symbol)?),
+                BinaryOp::Mul => number_value(number(left, symbol)? * number(right, symbol)?),
+                BinaryOp::Div | BinaryOp::Rem => {
+                    let divisor = number(right, symbol)?;
+                    if divisor == 0.0 {
+                        return Err("division by zero".to_string());
+                    }
+                    let dividend = number(left, symbol)?;
+                    number_value(if op == BinaryOp::Div {
+                        dividend / divisor
+                    } else {
+                        dividend % divisor
+                    })
+                }
+                _ => unreachable!("handled above"),
+            }
+        }),
+    }
+}
+
+/// Applies an operation to two values, or item by item when either is a list.
+fn element_wise(
+    left: &Value,
+    right: &Value,
+    apply: impl Fn(&Value, &Value) -> Result<Value, String>,
+) -> Result<Value, String> {
+    match (left, right) {
+        (Value::Array(left), Value::Array(right)) => {
+            if left.len() != right.len() {
+                return Err(format!(
+                    "lists of different lengths ({} and {}) cannot be combined",
+                    left.len(),
+                    right.len()
+                ));
+            }
+            left.iter()
+                .zip(right)
+                .map(|(left, right)| apply(left, right))
+                .collect::<Result<_, _>>()
+                .map(Value::Array)
+        }
+        (Value::Array(left), right) => left
+            .iter()
+            .map(|left| apply(left, right))
+            .collect::<Result<_, _>>()
+            .map(Value::Array),
+        (left, Value::Array(right)) => right
+            .iter()
+            .map(|right| apply(left, right))
+            .collect::<Result<_, _>>()
+            .map(Value::Array),
+        (left, right) => apply(left, right),
+    }
+}
+
+/// Calls a built-in function.
+fn call(function: Function, arguments: &[Value]) -> Result<Value, String> {
+    let name = function.name();
+    let numbers = |value: &Value| -> Result<Vec<f64>, String> {
+        list(value, name)?
+            .iter()
+            .map(|item| number(item, name))
+            .collect()
+    };
+    match (function, arguments) {
+        (Function::Sum, [items]) => number_value(numbers(items)?.iter().sum()),
+        (Function::Avg, [items]) => {
+            let items = numbers(items)?;
+            if items.is_empty() {
+                return Ok(Value::Null);
+            }
+            number_value(items.iter().sum::<f64>() / items.len() as f64)
+        }
+        (Function::Min | Function::Max, arguments) => {
+            let items = match arguments {
+                [items] => numbers(items)?,
+                numbers => numbers
+                    .iter()
+                    .map(|item| number(item, name))
+                    .collect::<Result<_, _>>()?,
+            };
+            let fold = if function == Function::Min {
+                f64::min
+            } else {
+                f64::max
+            };
+            match items.into_iter().reduce(fold) {
+                Some(result) => number_value(result),
+                None => Ok(Value::Null),
+            }
+        }
+        (Function::Len, [Value::String(text)]) => number_value(text.chars().count() as f64),
+        (Function::Count | Function::Len, [items]) => number_value(list(items, name)?.len() as f64),
+        (Function::All, [items]) => list(items, name)?
+            .iter()
+            .try_fold(true, |all, item| Ok(all && boolean(item)?))
+            .map(Value::Bool),
+        (Function::Any, [items]) => list(items, name)?
+            .iter()
+            .try_fold(false, |any, item| Ok(any || boolean(item)?))
+            .map(Value::Bool),
+        (Function::Lower, [text]) => Ok(Value::String(string(text, name)?.to_lowercase())),
+        (Function::Upper, [text]) => Ok(Value::String(string(text, name)?.to_uppercase())),
+        (Function::Trim, [text]) => Ok(Value::String(string(text, name)?.trim().to_string())),
+        (Function::Contains, [Value::Array(items), item]) => Ok(Value::Bool(
+            items.iter().any(|candidate| equals(candidate, item)),
+        )),
+        (Function::Contains, [text, part]) => Ok(Value::Bool(
+            string(text, name)?.contains(string(part, name)?),
+        )),
+        (Function::StartsWith, [text, prefix]) => Ok(Value::Bool(
+            string(text, name)?.starts_with(string(prefix, name)?),
+        )),
+        (Function::EndsWith, [text, suffix]) => Ok(Value::Bool(
+            string(text, name)?.ends_with(string(suffix, name)?),
+        )),
+        (Function::Abs, [value]) => number_value(number(value, name)?.abs()),
+        (Function::Round, [value]) => number_value(number(value, name)?.round()),
+        (Function::Floor, [value]) => number_value(number(value, name)?.floor()),
+        (Function::Ceil, [value]) => number_value(number(value, name)?.ceil()),
+        _ => Err(format!("invalid arguments for {}", name)),
+    }
+}
+
+/// Compares two values, treating numbers of any representation as equal when they are.
+fn equals(left: &Value, right: &Value) -> bool {
+    match (left, right) {
+        (Value::Number(left), Value::Number(right)) => left.as_f64() == right.as_f64(),
+        (Value::Array(left), Value::Array(right)) => {
+            left.len() == right.len() && left.iter().zip(right).all(|(l, r)| equals(l, r))
+        }
+        _ => left == right,
+    }
+}
+
+/// Orders two numbers, or two strings: RFC 3339 date-times chronologically and other strings
+/// lexicographically.
+fn order(left: &Value, right: &Value, symbol: &str) -> Result<Ordering, String> {
+    let ordering = match (left, right) {
+        (Value::Number(_), Value::Number(_)) => {
+            number(left, symbol)?.partial_cmp(&number(right, symbol)?)
+        }
+        (Value::String(left), Value::String(right)) => Some(
+            match (
+                datetime::parse_rfc3339(left),
+                datetime::parse_rfc3339(right),
+            ) {
+                (Some(left), Some(right)) => left.cmp(&right),
+                _ => left.cmp(right),
+            },
+        ),
+        _ => None,
+    };
+    ordering.ok_or_else(|| format!("'{}' cannot compare {} with {}", symbol, left, right))
+}
+
+fn boolean(value: &Value) -> Result<bool, String> {
+    value
+        .as_bool()
+        .ok_or_else(|| format!("expected a boolean, got {}", value))
+}
+
+fn number(value: &Value, operation: &str) -> Result<f64, String> {
+    value
+        .as_f64()
+        .ok_or_else(|| format!("'{}' expected a number, got {}", operation, value))
+}
+
+fn string<'v>(value: &'v Value, operation: &str) -> Result<&'v str, String> {
+    value
+        .as_str()
+        .ok_or_else(|| format!("'{}' expected a string, got {}", operation, value))
+}
+
+fn list<'v>(value: &'v Value, operation: &str) -> Result<&'v Vec<Value>, String> {
+    value
+        .as_array()
+        .ok_or_else(|| format!("'{}' expected a list, got {}", operation, value))
+}
+
+/// Converts a number to a JSON value, as an integer when it is one.
+fn number_value(number: f64) -> Result<Value, String> {
+    if !number.is_finite() {
+        return Err(format!("{} is not a valid number", number));
+    }
+    if number.fract() == 0.0 && number.abs() < 9_007_199_254_740_992.0 {
+        return Ok(Value::from(number as i64));
+    }
+    Ok(Value::from(number))
+}
@@ -0,0 +1,215 @@
+//! A [`StateStore`] keeping one JSON file per instance.
+
+use crate::{Error, Snapshot, StateStore};
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// How many times a locked instance is retried before giving up.
+const LOCK_ATTEMPTS: u32 = 50;
+
+/// How long a lock may be held before it is considered left behind by a crashed process. A
+/// compare-and-swap only holds it to read and write one small file.
+const STALE_LOCK_AGE: Duration = Duration::from_secs(10);
+
+/// Tells apart the locks taken by the same process within the same millisecond.
+static LOCK_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// A store writing each instance to `<directory>/<instance_id>.json`.
+///
+/// Writes go to a temporary file that is then renamed over the snapshot, so a crash never
+/// leaves a partially written snapshot behind. Compare-and-swap holds an
+/// `<instance_id>.lock` file while it reads and replaces the snapshot, which makes it safe
+/// across processes sharing the directory. The lock file records the process id of its owner
+/// and when it was taken; a lock older than ten seconds was left behind by a crashed process
+/// and is broken by the next writer.
+#[derive(Debug, Clone)]
+pub struct FileStore {
+    directory: PathBuf,
+}
+
+impl FileStore {
+    /// Creates a store in the given directory, creating the directory if needed.
+    pub fn new(directory: impl Into<PathBuf>) -> Result<Self, Error> {
+        let directory = directory.into();
+        fs::create_dir_all(&directory).map_err(|e| Self::io_error(&directory, e))?;
+        Ok(FileStore { directory })
+    }
+
+    /// Returns the path of a file belonging to an instance, rejecting ids that are not plain
+    /// file names.
+    fn path(&self, instance_id: &str, extension: &str) -> Result<PathBuf, Error> {
+        if instance_id.is_empty()
+            || instance_id.starts_with('.')
+            || instance_id.contains(['/', '\\'])
+        {
+            return Err(Error::Store(format!(
+                "Invalid instance id '{}'.",
+                instance_id
+            )));
+        }
+        Ok(self
+            .directory
+            .join(format!("{}.{}", instance_id, extension)))
+    }
+
+    fn io_error(path: &Path, error: std::io::Error) -> Error {
+        Error::Store(format!("{}: {}", path.display(), error))
+    }
+
+    /// Writes a snapshot through a temporary file and an atomic rename.
+    fn write(&self, instance_id: &str, snapshot: &Snapshot) -> Result<(), Error> {
+        let path = self.path(instance_id, "json")?;
+        let temp_path = self.path(instance_id, "json.tmp")?;
+        let content =
+            serde_json::to_vec_pretty(snapshot).map_err(|e| Error::Store(e.to_string()))?;
+        fs::write(&temp_path, content).map_err(|e| Self::io_error(&temp_path, e))?;
+        fs::rename(&temp_path, &path).map_err(|e| Self::io_error(&path, e))
+    }
+
+    /// Creates the lock file of an instance, waiting for a concurrent writer to release it and
+    /// breaking it if its owner has held it for too long.
+    fn lock(&self, instance_id: &str) -> Result<LockFile, Error> {
+        let path = self.path(instance_id, "lock")?;
+        let owner = Self::lock_owner();
+        for _ in 0..LOCK_ATTEMPTS {
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(mut file) => {
+                    if let Err(error) = file.write_all(owner.as_bytes()) {
+                        let _ = fs::remove_file(&path);
+                        return Err(Self::io_error(&path, error));
+                    }
+                    return Ok(LockFile { path, owner });
+                }
+                Err(error) if error.kind() == ErrorKind::AlreadyExists => {
+                    if !self.break_stale_lock(instance_id, &path)? {
+                        thread::sleep(Duration::from_millis(10));
+                    }
+                }
+                Err(error) => return Err(Self::io_error(&path, error)),
+            }
+        }
+        Err(Error::Store(format!(
+            "{}: instance is locked by another writer",
+            path.display()
+        )))
+    }
+
+    /// Returns the content of a new lock file: the process id, the time in milliseconds since
+    /// the Unix epoch and a counter making it unique.
+    fn lock_owner() -> String {
+        format!(
+            "{} {} {}",
+            std::process::id(),
+            Self::elapsed_ms(SystemTime::now()),
+            LOCK_COUNTER.fetch_add(1, Ordering::Relaxed)
+        )
+    }
+
+    fn elapsed_ms(time: SystemTime) -> u64 {
+        time.duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_millis() as u64)
+    }
+
+    /// Removes the lock file at `path` if it is older than [`STALE_LOCK_AGE`], and returns
+    /// whether it did.
+    fn break_stale_lock(&self, instance_id: &str, path: &Path) -> Result<bool, Error> {
+        let Ok(owner) = fs::read_to_string(path) else {
+            return Ok(false); // Released in the meantime
+        };
+        // A lock file that is still being written has no timestamp yet; use its creation time
+        let taken_ms = match owner.split_whitespace().nth(1).map(str::parse::<u64>) {
+            Some(Ok(taken_ms)) => taken_ms,
+            _ => match fs::metadata(path).and_then(|metadata| metadata.modified()) {
+                Ok(modified) => Self::elapsed_ms(modified),
+                Err(_) => return Ok(false),
+            },
+        };
+        let age_ms = Self::elapsed_ms(SystemTime::now()).saturating_sub(taken_ms);
+        if age_ms <= STALE_LOCK_AGE.as_millis() as u64 {
+            return Ok(false);
+        }
+
+        // Move the lock aside atomically, so that only one writer breaks it
+        let stale_path = self.path(
+            instance_id,
+            &format!(
+                "lock.{}-{}.stale",
+                std::process::id(),
+                LOCK_COUNTER.fetch_add(1, Ordering::Relaxed)
+            ),
+        )?;
+        if fs::rename(path, &stale_path).is_err() {
+            return Ok(false);
+        }
+        if fs::read_to_string(&stale_path).ok().as_deref() != Some(owner.as_str()) {
+            // Another writer broke the lock and took a new one first: put it back
+            let _ = fs::hard_link(&stale_path, path);
+            let _ = fs::remove_file(&stale_path);
+            return Ok(false);
+        }
+        log::warn!(
+            "Broke the lock {} held by '{}' for {} ms",
+            path.display(),
+            owner,
+            age_ms
+        );
+        let _ = fs::remove_file(&stale_path);
+        Ok(true)
+    }
+}
+
+/// Removes the lock file when the compare-and-swap is over, unless it was broken meanwhile.
+struct LockFile {
+    path: PathBuf,
+    owner: String,
+}
+
+impl Drop for LockFile {
+    fn drop(&mut self) {
+        if fs::read_to_string(&self.path).ok().as_deref() != Some(self.owner.as_str()) {
+            log::warn!("Lock {} was broken while it was held", self.path.display());
+            return;
+        }
+        if let Err(error) = fs::remove_file(&self.path) {
+            log::warn!("Failed to remove {}: {}", self.path.display(), error);
+        }
+    }
+}
+
+impl StateStore for FileStore {
+    fn load(&self, instance_id: &str) -> Result<Option<Snapshot>, Error> {
+        let path = self.path(instance_id, "json")?;
+        let content = match fs::read(&path) {
+            Ok(content) => content,
+            Err(error) if error.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(error) => return Err(Self::io_error(&path, error)),
+        };
+        serde_json::from_slice(&content)
+            .map(Some)
+            .map_err(|e| Error::Store(format!("{}: {}", path.display(), e)))
+    }
+
+    fn save(&self, instance_id: &str, snapshot: &Snapshot) -> Result<(), Error> {
+        let _lock = self.lock(instance_id)?;
+        self.write(instance_id, snapshot)
+    }
+
+    fn compare_and_swap(
+        &self,
+        instance_id: &str,
+        expected_version: u64,
+        snapshot: &Snapshot,
+    ) -> Result<bool, Error> {
+        let _lock = self.lock(instance_id)?;
+        let stored_version = self.load(instance_id)?.map_or(0, |s| s.version);
+        if stored_version != expected_version {
+            return Ok(false);
+        }
+        self.write(instance_id, snapshot)?;
+        Ok(true)
+    }
+}
@@ -0,0 +1,102 @@
+//! Action handlers, registered per action type.
+
+use crate::{Action, ActionError};
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+
+/// An async action handler, receiving the action, the memory, the context and the event
+/// payload.
+pub(crate) type ActionHandler<C> = dyn for<'a> Fn(
+        &'a Action,
+        &'a mut Map<String, Value>,
+        &'a mut C,
+        &'a Value,
+    ) -> Pin<Box<dyn Future<Output = Result<(), ActionError>> + Send + 'a>>
+    + Send
+    + Sync;
+
+/// The action handlers of a state machine, keyed by `action_type`.
+///
+/// Actions are dispatched to the handler registered for their type, or to the fallback
+/// handler if there is none. [`crate::StateMachine::new`] rejects configurations using an
+/// action type that no handler covers.
+pub struct HandlerRegistry<C> {
+    handlers: HashMap<String, Arc<ActionHandler<C>>>,
+    fallback: Option<Arc<ActionHandler<C>>>,
+}
+
+impl<C> Default for HandlerRegistry<C> {
+    fn default() -> Self {
+        HandlerRegistry {
+            handlers: HashMap::new(),
+            fallback: None,
+        }
+    }
+}
+
+impl<C> HandlerRegistry<C> {
+    /// Creates an empty registry.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers the handler for actions of the given type, replacing any previous one.
+    /// Built-in action types such as `set` or `increment` are reserved: registering a handler
+    /// for one makes [`crate::StateMachine::new`] fail with [`crate::Error::BuiltinHandler`].
+    pub fn with_handler<F>(mut self, action_type: impl Into<String>, handler: F) -> Self
+    where
+        F: for<'a> Fn(
+                &'a Action,
+                &'a mut Map<String, Value>,
+                &'a mut C,
+                &'a Value,
+            )
+                -> Pin<Box<dyn Future<Output = Result<(), ActionError>> + Send + 'a>>
+            + Send
+            + Sync
+            + 'static,
+    {
+        self.handlers.insert(action_type.into(), Arc::new(handler));
+        self
+    }
+
+    /// Sets the handler for actions whose type has no registered handler.
+    pub fn with_fallback<F>(mut self, handler: F) -> Self
+    where
+        F: for<'a> Fn(
+                &'a Action,
+                &'a mut Map<String, Value>,
+                &'a mut C,
+                &'a Value,
+            )
+                -> Pin<Box<dyn Future<Output = Result<(), ActionError>> + Send + 'a>>
+            + Send
+            + Sync
+            + 'static,
+    {
+        self.fallback = Some(Arc::new(handler));
+        self
+    }
+
+    /// Returns whether actions of the given type have a handler, either registered for the
+    /// type or the fallback.
+    pub fn handles(&self, action_type: &str) -> bool {
+        self.get(action_type).is_some()
+    }
+
+    /// Returns the action types that have a handler registered for them.
+    pub(crate) fn action_types(&self) -> impl Iterator<Item = &str> {
+        self.handlers.keys().map(String::as_str)
+    }
+
+    /// Returns the handler for actions of the given type.
+    pub(crate) fn get(&self, action_type: &str) -> Option<&ActionHandler<C>> {
+        self.handlers
+            .get(action_type)
+            .or(self.fallback.as_ref())
+            .map(|handler| handler.as_ref())
+    }
+}
@@ -0,0 +1,116 @@
+//! An append-only record of the steps taken by a state machine, used to audit and replay it.
+
+use crate::{Action, Snapshot};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+/// What caused a journaled step.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum JournalTrigger {
+    /// An event passed to `trigger` or `trigger_with`.
+    Event {
+        /// The event name.
+        name: String,
+        /// The event payload (`null` for `trigger`).
+        payload: Value,
+    },
+    /// A round of eventless transitions following another step.
+    Eventless,
+    /// A timed transition that became due.
+    Timer {
+        /// The state that declares the timed transition.
+        state: String,
+        /// The position of the transition in the state's `after` list.
+        index: usize,
+    },
+}
+
+/// The top-level memory fields changed by a step.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct MemoryDiff {
+    /// Fields that were added or changed, with their new values.
+    pub set: Map<String, Value>,
+    /// Fields that were removed.
+    pub removed: Vec<String>,
+}
+
+impl MemoryDiff {
+    /// Computes the changes that turn `before` into `after`.
+    pub(crate) fn between(before: &Map<String, Value>, after: &Map<String, Value>) -> Self {
+        MemoryDiff {
+            set: after
+                .iter()
+                .filter(|(key, value)| before.get(*key) != Some(*value))
+                .map(|(key, value)| (key.clone(), value.clone()))
+                .collect(),
+            removed: before
+                .keys()
+                .filter(|key| !after.contains_key(*key))
+                .cloned()
+                .collect(),
+        }
+    }
+
+    /// Applies the changes to `memory`.
+    pub(crate) fn apply(&self, memory: &mut Map<String, Value>) {
+        for key in &self.removed {
+            memory.remove(key);
+        }
+        for (key, value) in &self.set {
+            memory.insert(key.clone(), value.clone());
+        }
+    }
+}
+
+/// A single journaled step.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JournalEntry {
+    /// The version of the state machine after the step.
+    pub version: u64,
+    /// When the step was taken, in milliseconds since the Unix epoch.
+    pub timestamp_ms: u64,
+    /// What caused the step.
+    pub trigger: JournalTrigger,
+    /// The active leaf states before the step.
+    pub from: Vec<String>,
+    /// The active leaf states after the step.
+    pub to: Vec<String>,
+    /// The changes the step made to the memory.
+    pub memory_diff: MemoryDiff,
+    /// The exit, transition and entry actions of the step, in execution order.
+    pub actions: Vec<Action>,
+    /// The action error, when the step was redirected to an `on_error` state.
+    pub error: Option<String>,
+}
+
+/// The journal of a state machine: the snapshot it started from and every step taken since.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Journal {
+    /// The state machine before the first journaled step.
+    pub start: Snapshot,
+    /// The steps taken, oldest first.
+    pub entries: Vec<JournalEntry>,
+}
+
+/// A journaled step whose replay did not end in the recorded states.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Divergence {
+    /// The version of the journal entry.
+    pub version: u64,
+    /// The active leaf states recorded in the journal.
+    pub recorded: Vec<String>,
+    /// The active leaf states reached by the replay.
+    pub replayed: Vec<String>,
+    /// Why the replay failed, if it could not take the step at all.
+    pub error: Option<String>,
+}
+
+/// The outcome of [`crate::StateMachine::replay`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ReplayReport {
+    /// The rebuilt instance, which can be resumed with [`crate::StateMachine::restore`].
+    pub snapshot: Snapshot,
+    /// The steps where the replay differed from the journal, in order.
+    pub divergences: Vec<Divergence>,
+}
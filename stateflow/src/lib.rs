@@ -1,7 +1,41 @@
 //! A simple state machine library for Rust.
 
+mod builtins;
+mod clock;
+mod datetime;
+mod error;
+mod events;
+mod expr;
+#[cfg(feature = "file-store")]
+mod file_store;
+mod handlers;
+mod journal;
+mod operand;
+mod path;
+mod snapshot;
+#[cfg(feature = "sqlite-store")]
+mod sqlite_store;
+mod store;
+mod template;
+
+pub use clock::{Clock, ManualClock, SystemClock};
+pub use error::{ActionError, Error, Violation};
+pub use events::TransitionEvent;
+#[cfg(feature = "file-store")]
+pub use file_store::FileStore;
+pub use handlers::HandlerRegistry;
+pub use journal::{Divergence, Journal, JournalEntry, JournalTrigger, MemoryDiff, ReplayReport};
+pub use snapshot::{PendingTimer, Snapshot};
+#[cfg(feature = "sqlite-store")]
+pub use sqlite_store::SqliteStore;
+pub use store::{MemoryStore, StateStore};
+pub use template::TemplateMode;
+
+use expr::{Expression, RuleExpression};
 use lru::LruCache;
 use once_cell::sync::Lazy;
+use operand::Operand;
+use path::FieldPath;
 use serde::{Deserialize, Serialize};
 use serde_json::{Map, Value};
 use std::collections::HashMap;
@@ -10,40 +44,87 @@ use std::fmt::{self, Display, Formatter};
 use std::future::Future;
 use std::hash::{Hash, Hasher};
 use std::num::NonZero;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::{Arc, RwLock};
+use template::Template;
 use tokio::sync::RwLock as AsyncRwLock; // Alias to differentiate
+use tokio::sync::{broadcast, watch, Notify};
 
 /// Represents an action with a type and command.
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Action {
     /// The type of the action.
     pub action_type: String,
     /// The command to execute.
     pub command: String,
+    /// Structured parameters of the action (`null` when not set).
+    #[serde(default)]
+    pub params: Value,
 }
 
 /// A struct representing a state and its transitions, including actions on enter and exit.
 #[derive(Debug, Clone)]
 struct State {
     name: String,
+    parent: Option<String>,  // Enclosing state, if this state is nested
+    children: Vec<String>,   // Nested states, in declaration order
+    initial: Option<String>, // Child entered when this state is targeted directly
+    parallel: bool,          // Whether all children are active at once (orthogonal regions)
+    is_final: bool,          // Whether the machine is done once this state is reached
+    order: usize,            // Position in document order (parents before children)
     on_enter_actions: Vec<Action>,
     on_exit_actions: Vec<Action>,
-    transitions: HashMap<String, Transition>, // Key: event name, Value: Transition instance
-    validations: Vec<ValidationRule>,         // State validation rules
+    transitions: HashMap<String, Vec<Transition>>, // Key: event name, Value: candidates in declaration order
+    eventless: Vec<Transition>, // Transitions checked as soon as the state is entered
+    after: Vec<TimedTransition>, // Transitions taken once the state has been active long enough
+    validations: Vec<ValidationRule>, // State validation rules
 }
 
 /// Represents a transition between states, including actions and validations.
 #[derive(Debug, Clone)]
 struct Transition {
     to_state: String,
+    guard: Option<Condition>, // Must hold for the transition to be taken
     actions: Vec<Action>,
+    error_policy: ErrorPolicy, // What to do when one of the transition's actions fails
+    on_error: Option<String>,  // Target state for `ErrorPolicy::Redirect`
     validations: Vec<ValidationRule>, // Transition validation rules
 }
 
+/// A transition taken automatically once its state has been active for `delay_ms`.
+#[derive(Debug, Clone)]
+struct TimedTransition {
+    delay_ms: u64,
+    transition: Transition,
+}
+
+/// Determines what happens when an action fails while a transition is being taken.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum ErrorPolicy {
+    /// Stop, restore the memory and active states from before the transition and report the error.
+    #[default]
+    Abort,
+    /// Log the error and keep running the remaining actions.
+    Continue,
+    /// Stop and move to the transition's `on_error` state instead of its target.
+    Redirect,
+}
+
+/// A transition selected by an event, with the state that declares it and the states it exits
+/// (innermost first) and enters (outermost first).
+#[derive(Debug, Clone)]
+struct Microstep {
+    source: String,
+    transition: Transition,
+    exit_states: Vec<State>,
+    entry_states: Vec<State>,
+}
+
 /// Represents a validation rule applied to the memory.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 struct ValidationRule {
-    field: String,
+    field: FieldPath,
     rules: Vec<FieldRule>,
     condition: Option<Condition>, // Optional condition for conditional validations
 }
@@ -57,24 +138,320 @@ enum FieldRule {
     #[serde(rename = "nullable")]
     Nullable { is_nullable: bool },
     #[serde(rename = "min_value")]
-    MinValue { value: f64 },
+    MinValue { value: Operand },
     #[serde(rename = "max_value")]
-    MaxValue { value: f64 },
+    MaxValue { value: Operand },
     #[serde(rename = "editable")]
     Editable { is_editable: bool },
     #[serde(rename = "read_only")]
     ReadOnly { is_read_only: bool },
     #[serde(rename = "enum")]
     Enum { values: Vec<Value> },
+    #[serde(rename = "min_length")]
+    MinLength { value: Operand },
+    #[serde(rename = "max_length")]
+    MaxLength { value: Operand },
+    #[serde(rename = "pattern")]
+    Pattern { pattern: Pattern },
+    #[serde(rename = "format")]
+    Format { format: StringFormat },
+    #[serde(rename = "contains")]
+    Contains { value: Operand },
+    #[serde(rename = "unique_items")]
+    UniqueItems { is_unique: bool },
+    #[serde(rename = "expr")]
+    Expr { expr: RuleExpression },
     // Add more rules as needed
 }
 
-/// Represents a condition for conditional validations.
+/// A regular expression embedded in the configuration, compiled once when the configuration is
+/// loaded.
+#[derive(Debug, Clone)]
+struct Pattern(regex::Regex);
+
+impl Serialize for Pattern {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.0.as_str().serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Pattern {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let pattern = String::deserialize(deserializer)?;
+        regex::Regex::new(&pattern)
+            .map(Pattern)
+            .map_err(|e| serde::de::Error::custom(format!("invalid pattern: {}", e)))
+    }
+}
+
+/// A well-known string format checked by the `format` rule.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum StringFormat {
+    Email,
+    Uuid,
+    DateTime,
+}
+
+impl StringFormat {
+    /// Returns the name of the format as written in the configuration.
+    fn name(self) -> &'static str {
+        match self {
+            StringFormat::Email => "email",
+            StringFormat::Uuid => "uuid",
+            StringFormat::DateTime => "date-time",
+        }
+    }
+
+    /// Returns whether the string is valid in this format, using the JSON Schema format checks.
+    fn is_valid(self, value: &Value) -> bool {
+        static VALIDATORS: Lazy<HashMap<&'static str, jsonschema::Validator>> = Lazy::new(|| {
+            [
+                StringFormat::Email,
+                StringFormat::Uuid,
+                StringFormat::DateTime,
+            ]
+            .into_iter()
+            .map(|format| {
+                let validator = jsonschema::options()
+                    .with_draft(jsonschema::Draft::Draft202012)
+                    .should_validate_formats(true)
+                    .build(&serde_json::json!({ "format": format.name() }))
+                    .expect("Failed to compile format schema");
+                (format.name(), validator)
+            })
+            .collect()
+        });
+        VALIDATORS[self.name()].is_valid(value)
+    }
+}
+
+/// A memory field that actions must leave unchanged, from a `read_only` or `editable` rule.
+#[derive(Debug, Clone)]
+struct ProtectedField {
+    field: FieldPath,
+    rule: &'static str,
+}
+
+/// Represents a condition for conditional validations and guards: a comparison, an
+/// expression, or a combination of nested conditions.
+#[derive(Debug, Clone, Serialize)]
+#[serde(untagged)]
+enum Condition {
+    /// Holds when every nested condition holds.
+    All { all: Vec<Condition> },
+    /// Holds when at least one nested condition holds.
+    Any { any: Vec<Condition> },
+    /// Holds when the nested condition does not hold.
+    Not { not: Box<Condition> },
+    /// Holds when the comparison holds.
+    Compare(Comparison),
+    /// Holds when the expression yields `true`.
+    Expr(Expression),
+}
+
+impl<'de> Deserialize<'de> for Condition {
+    /// Picks the kind of condition from its shape, so that errors in a nested comparison or
+    /// expression are reported as such.
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = Value::deserialize(deserializer)?;
+        let key = |key: &str| {
+            value
+                .as_object()
+                .and_then(|fields| fields.get(key).cloned())
+        };
+        let condition = if let Value::String(text) = &value {
+            Expression::parse(text).map(Condition::Expr)
+        } else if let Some(all) = key("all") {
+            serde_json::from_value(all)
+                .map(|all| Condition::All { all })
+                .map_err(|e| e.to_string())
+        } else if let Some(any) = key("any") {
+            serde_json::from_value(any)
+                .map(|any| Condition::Any { any })
+                .map_err(|e| e.to_string())
+        } else if let Some(not) = key("not") {
+            serde_json::from_value(not)
+                .map(|not| Condition::Not { not })
+                .map_err(|e| e.to_string())
+        } else {
+            serde_json::from_value(value)
+                .map(Condition::Compare)
+                .map_err(|e| e.to_string())
+        };
+        condition.map_err(serde::de::Error::custom)
+    }
+}
+
+/// Compares a field of the memory or the event payload with a value.
 #[derive(Debug, Clone, Serialize, Deserialize)]
-struct Condition {
-    field: String,
-    operator: String,
-    value: Value,
+#[serde(try_from = "RawComparison")]
+struct Comparison {
+    field: FieldPath,
+    operator: Operator,
+    value: Operand,
+    #[serde(skip_serializing)]
+    pattern: Option<Pattern>, // Compiled from a literal value for `matches`
+}
+
+/// A comparison as written in the configuration, checked when it is converted.
+#[derive(Deserialize)]
+struct RawComparison {
+    field: FieldPath,
+    operator: Operator,
+    value: Option<Operand>,
+}
+
+impl TryFrom<RawComparison> for Comparison {
+    type Error = String;
+
+    fn try_from(raw: RawComparison) -> Result<Self, Self::Error> {
+        let operator = raw.operator;
+        let value = match (operator, raw.value) {
+            (Operator::Exists | Operator::Missing, _) => Operand::Literal(Value::Null),
+            (_, None) => {
+                return Err(format!(
+                    "condition on '{}' needs a value for operator '{}'",
+                    raw.field, operator
+                ))
+            }
+            (Operator::In | Operator::NotIn, Some(Operand::Literal(value)))
+                if !value.is_array() =>
+            {
+                return Err(format!(
+                    "operator '{}' needs an array value, got {}",
+                    operator, value
+                ))
+            }
+            (
+                Operator::Matches | Operator::StartsWith | Operator::EndsWith,
+                Some(Operand::Literal(value)),
+            ) if !value.is_string() => {
+                return Err(format!(
+                    "operator '{}' needs a string value, got {}",
+                    operator, value
+                ))
+            }
+            (_, Some(value)) => value,
+        };
+        let pattern = match (operator, value.literal()) {
+            (Operator::Matches, Some(Value::String(pattern))) => Some(Pattern(
+                regex::Regex::new(pattern).map_err(|e| format!("invalid pattern: {}", e))?,
+            )),
+            _ => None,
+        };
+        Ok(Comparison {
+            field: raw.field,
+            operator,
+            value,
+            pattern,
+        })
+    }
+}
+
+/// The operator of a comparison.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+enum Operator {
+    #[serde(rename = "==")]
+    Eq,
+    #[serde(rename = "!=")]
+    Ne,
+    #[serde(rename = ">")]
+    Gt,
+    #[serde(rename = "<")]
+    Lt,
+    #[serde(rename = ">=")]
+    Ge,
+    #[serde(rename = "<=")]
+    Le,
+    #[serde(rename = "in")]
+    In,
+    #[serde(rename = "not_in")]
+    NotIn,
+    #[serde(rename = "contains")]
+    Contains,
+    #[serde(rename = "exists")]
+    Exists,
+    #[serde(rename = "missing")]
+    Missing,
+    #[serde(rename = "matches")]
+    Matches,
+    #[serde(rename = "starts_with")]
+    StartsWith,
+    #[serde(rename = "ends_with")]
+    EndsWith,
+}
+
+impl Display for Operator {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Operator::Eq => "==",
+            Operator::Ne => "!=",
+            Operator::Gt => ">",
+            Operator::Lt => "<",
+            Operator::Ge => ">=",
+            Operator::Le => "<=",
+            Operator::In => "in",
+            Operator::NotIn => "not_in",
+            Operator::Contains => "contains",
+            Operator::Exists => "exists",
+            Operator::Missing => "missing",
+            Operator::Matches => "matches",
+            Operator::StartsWith => "starts_with",
+            Operator::EndsWith => "ends_with",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl Display for Condition {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let join = |f: &mut Formatter<'_>, conditions: &[Condition], separator: &str| {
+            let parts: Vec<String> = conditions.iter().map(ToString::to_string).collect();
+            write!(f, "({})", parts.join(separator))
+        };
+        match self {
+            Condition::All { all } => join(f, all, " and "),
+            Condition::Any { any } => join(f, any, " or "),
+            Condition::Not { not } => write!(f, "not {}", not),
+            Condition::Compare(comparison) => match comparison.operator {
+                Operator::Exists | Operator::Missing => {
+                    write!(f, "{} {}", comparison.field, comparison.operator)
+                }
+                _ => write!(
+                    f,
+                    "{} {} {}",
+                    comparison.field, comparison.operator, comparison.value
+                ),
+            },
+            Condition::Expr(expression) => write!(f, "{}", expression),
+        }
+    }
+}
+
+/// A JSON schema embedded in the configuration, compiled once when the configuration is loaded.
+#[derive(Debug, Clone)]
+struct JsonSchema {
+    schema: Value,
+    validator: Arc<jsonschema::Validator>,
+}
+
+impl Serialize for JsonSchema {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.schema.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for JsonSchema {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let schema = Value::deserialize(deserializer)?;
+        let validator = jsonschema::Validator::new(&schema)
+            .map_err(|e| serde::de::Error::custom(format!("invalid JSON schema: {}", e)))?;
+        Ok(JsonSchema {
+            schema,
+            validator: Arc::new(validator),
+        })
+    }
 }
 
 /// Represents the configuration of a state machine loaded from JSON.
@@ -82,25 +459,70 @@ struct Condition {
 struct StateMachineConfig {
     states: Vec<StateConfig>,
     transitions: Vec<TransitionConfig>,
+    #[serde(default)]
+    event_schemas: HashMap<String, JsonSchema>, // Key: event name, Value: payload schema
+    #[serde(default)]
+    action_schemas: HashMap<String, JsonSchema>, // Key: action type, Value: params schema
+    #[serde(skip)]
+    fingerprint: u64, // Stable digest of the parsed configuration, recorded in snapshots
+}
+
+impl StateMachineConfig {
+    /// Returns every action declared by the states and transitions.
+    fn actions(&self) -> impl Iterator<Item = &ActionConfig> {
+        let state_actions = self.states.iter().flat_map(|state| {
+            let after_actions = state.after.iter().flat_map(|after| &after.actions);
+            state
+                .on_enter_actions
+                .iter()
+                .chain(&state.on_exit_actions)
+                .chain(after_actions)
+        });
+        let transition_actions = self
+            .transitions
+            .iter()
+            .flat_map(|transition| &transition.actions);
+        state_actions.chain(transition_actions)
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 struct StateConfig {
     name: String,
+    parent: Option<String>,
+    initial: Option<String>,
+    #[serde(default)]
+    parallel: bool,
+    #[serde(default, rename = "final")]
+    is_final: bool,
     #[serde(default)]
     on_enter_actions: Vec<ActionConfig>,
     #[serde(default)]
     on_exit_actions: Vec<ActionConfig>,
     validations: Option<Vec<ValidationRule>>,
+    #[serde(default)]
+    after: Vec<AfterConfig>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct AfterConfig {
+    duration_ms: u64,
+    to: String,
+    #[serde(default)]
+    actions: Vec<ActionConfig>, // Actions triggered during the timed transition
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 struct TransitionConfig {
     from: String,
-    event: String,
+    event: Option<String>, // Eventless when omitted
     to: String,
+    guard: Option<Condition>,
     #[serde(default)]
     actions: Vec<ActionConfig>, // Actions triggered during the transition
+    #[serde(default)]
+    error_policy: ErrorPolicy,
+    on_error: Option<String>,
     validations: Option<Vec<ValidationRule>>,
 }
 
@@ -108,16 +530,10 @@ struct TransitionConfig {
 struct ActionConfig {
     action_type: String,
     command: String,
+    #[serde(default)]
+    params: Value,
 }
 
-type ActionHandler<C> = dyn for<'a> Fn(
-        &'a Action,
-        &'a mut Map<String, Value>,
-        &'a mut C,
-    ) -> std::pin::Pin<Box<dyn Future<Output = ()> + Send + 'a>>
-    + Send
-    + Sync;
-
 /// Define environment variable name and default cache size
 const LRU_CACHE_SIZE_ENV_KEY: &str = "STATEFLOW_LRU_CACHE_SIZE";
 const DEFAULT_CACHE_SIZE: usize = 100;
@@ -142,11 +558,40 @@ static CONFIG_CACHE: Lazy<RwLock<LruCache<u64, Arc<StateMachineConfig>>>> = Lazy
     RwLock::new(LruCache::new(NonZero::new(cache_size).unwrap()))
 });
 
+/// How broken validation rules are reported.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ValidationMode {
+    /// The first broken rule fails the step with [`Error::Validation`].
+    #[default]
+    FailFast,
+    /// Every state and transition validation of the step is evaluated, and the broken rules
+    /// fail the step together with [`Error::Validations`].
+    CollectAll,
+}
+
 /// The state machine containing all states, the current state, memory, context, and handlers.
 pub struct StateMachine<'a, C> {
     states: Arc<RwLock<HashMap<String, State>>>,
-    current_state: Arc<RwLock<String>>,
-    action_handler: Arc<ActionHandler<C>>,
+    active_states: Arc<RwLock<Vec<String>>>, // Active leaf states, in document order
+    handlers: HandlerRegistry<C>,
+    event_schemas: HashMap<String, JsonSchema>,
+    action_schemas: HashMap<String, JsonSchema>,
+    clock: Arc<dyn Clock>,
+    timers: Arc<RwLock<Vec<PendingTimer>>>, // Timed transitions of the active states
+    timers_changed: Arc<Notify>,
+    completed: Arc<Notify>, // Notified when the machine reaches its final states
+    config_fingerprint: u64,
+    version: Arc<AtomicU64>,                      // Number of completed steps
+    timers_restored: bool,                        // Whether the pending timers come from a snapshot
+    store: Option<(Arc<dyn StateStore>, String)>, // Store and instance id each step is saved to
+    journal: Option<Arc<RwLock<Option<Journal>>>>, // Started by the first step once enabled
+    events: broadcast::Sender<TransitionEvent>,
+    current_state: watch::Sender<Vec<String>>, // Active leaf states, published after each step
+    max_eventless_steps: usize,
+    template_mode: TemplateMode,
+    validation_mode: ValidationMode,
+    replaying: bool,     // Whether actions are turned off to replay a journal
+    settled: AtomicBool, // Whether the eventless transitions of the initial states were followed
     /// The memory used by the state machine to store data.
     pub memory: Arc<AsyncRwLock<Map<String, Value>>>,
     /// The context used by the state machine to store state.
@@ -154,34 +599,37 @@ pub struct StateMachine<'a, C> {
     _marker: std::marker::PhantomData<&'a ()>, // To tie the lifetime to the struct
 }
 
+impl TransitionConfig {
+    /// Returns the event name used in error messages.
+    fn event_label(&self) -> &str {
+        self.event.as_deref().unwrap_or("<eventless>")
+    }
+}
+
+/// Number of transition events buffered for subscribers that fall behind.
+const EVENT_CHANNEL_CAPACITY: usize = 256;
+
+/// Default number of consecutive eventless steps after which a trigger is considered to loop.
+const DEFAULT_MAX_EVENTLESS_STEPS: usize = 100;
+
 impl<C> StateMachine<'_, C> {
     /// Creates a new state machine from a JSON configuration string.
-    pub fn new<F>(
+    ///
+    /// Every `action_type` used by the configuration must be covered by `handlers`, either by
+    /// a handler registered for it or by the fallback handler.
+    pub fn new(
         config_content: &str,
         initial_state: Option<String>,
-        action_handler: F,
+        handlers: HandlerRegistry<C>,
         memory: Map<String, Value>,
         context: C,
-    ) -> Result<Self, String>
-    where
-        F: for<'b> Fn(
-                &'b Action,
-                &'b mut Map<String, Value>,
-                &'b mut C,
-            ) -> std::pin::Pin<Box<dyn Future<Output = ()> + Send + 'b>>
-            + Send
-            + Sync
-            + 'static,
-    {
-        // Compute the hash of the config_content
-        let mut hasher = std::collections::hash_map::DefaultHasher::new();
-        config_content.hash(&mut hasher);
-        let config_hash = hasher.finish();
+    ) -> Result<Self, Error> {
+        let cache_key = Self::cache_key(config_content);
 
         // Try to get the cached config
         let config: Arc<StateMachineConfig> = {
             let mut cache = CONFIG_CACHE.write().unwrap();
-            if let Some(cached_config) = cache.get(&config_hash) {
+            if let Some(cached_config) = cache.get(&cache_key) {
                 cached_config.clone()
             } else {
                 // Parse and validate the config
@@ -190,75 +638,374 @@ impl<C> StateMachine<'_, C> {
 
                 // Parse the configuration from the provided string
                 let config_value: serde_json::Value = serde_json::from_str(config_content)
-                    .map_err(|err| format!("Invalid JSON format in configuration: {}", err))?;
+                    .map_err(|err| {
+                        Error::ConfigParse(format!("Invalid JSON format in configuration: {}", err))
+                    })?;
 
                 // Validate the configuration against the schema
-                let compiled_schema = jsonschema::Validator::new(&schema)
-                    .map_err(|e| format!("Failed to compile JSON schema: {}", e))?;
+                let compiled_schema = jsonschema::Validator::new(&schema).map_err(|e| {
+                    Error::SchemaViolation(format!("Failed to compile JSON schema: {}", e))
+                })?;
                 if let Err(error) = compiled_schema.validate(&config_value) {
-                    return Err(format!(
-                        "JSON configuration does not conform to schema: {}",
-                        error
-                    ));
+                    return Err(Error::SchemaViolation(error.to_string()));
                 }
 
                 // Deserialize the configuration
-                let config_deserialized: StateMachineConfig = serde_json::from_value(config_value)
-                    .map_err(|err| format!("Failed to deserialize configuration: {}", err))?;
+                let fingerprint = snapshot::config_fingerprint(&config_value);
+                let mut config_deserialized: StateMachineConfig =
+                    serde_json::from_value(config_value).map_err(|err| {
+                        Error::ConfigParse(format!("Failed to deserialize configuration: {}", err))
+                    })?;
+                config_deserialized.fingerprint = fingerprint;
 
                 // Validate the config
                 Self::validate_config(&config_deserialized)?;
 
                 // Cache the config
                 let config_arc = Arc::new(config_deserialized);
-                cache.put(config_hash, config_arc.clone());
+                cache.put(cache_key, config_arc.clone());
                 config_arc
             }
         };
 
+        Self::check_handlers(&config, &handlers)?;
+
         // Now proceed to create the StateMachine using `config`
         // Create states and populate transitions
         let mut states = HashMap::new();
         for state_config in &config.states {
             let state = State {
                 name: state_config.name.clone(),
+                parent: state_config.parent.clone(),
+                children: config
+                    .states
+                    .iter()
+                    .filter(|child| child.parent.as_ref() == Some(&state_config.name))
+                    .map(|child| child.name.clone())
+                    .collect(),
+                initial: state_config.initial.clone(),
+                parallel: state_config.parallel,
+                is_final: state_config.is_final,
+                order: 0,
                 on_enter_actions: Self::create_actions(&state_config.on_enter_actions),
                 on_exit_actions: Self::create_actions(&state_config.on_exit_actions),
                 transitions: HashMap::new(),
+                eventless: Vec::new(),
+                after: state_config
+                    .after
+                    .iter()
+                    .map(|after| TimedTransition {
+                        delay_ms: after.duration_ms,
+                        transition: Transition {
+                            to_state: after.to.clone(),
+                            guard: None,
+                            actions: Self::create_actions(&after.actions),
+                            error_policy: ErrorPolicy::Abort,
+                            on_error: None,
+                            validations: Vec::new(),
+                        },
+                    })
+                    .collect(),
                 validations: state_config.validations.clone().unwrap_or_default(),
             };
             states.insert(state_config.name.clone(), state);
         }
 
+        // Number the states in document order: a depth-first walk of the hierarchy, visiting
+        // children in declaration order, so that ancestors always sort before descendants
+        let mut pending: Vec<String> = config
+            .states
+            .iter()
+            .rev()
+            .filter(|state| state.parent.is_none())
+            .map(|state| state.name.clone())
+            .collect();
+        let mut order = 0;
+        while let Some(name) = pending.pop() {
+            if let Some(state) = states.get_mut(&name) {
+                state.order = order;
+                order += 1;
+                pending.extend(state.children.iter().rev().cloned());
+            }
+        }
+
         // Populate transitions for each state
         for transition_config in &config.transitions {
             if let Some(state) = states.get_mut(&transition_config.from) {
                 let transition = Transition {
                     to_state: transition_config.to.clone(),
+                    guard: transition_config.guard.clone(),
                     actions: Self::create_actions(&transition_config.actions),
+                    error_policy: transition_config.error_policy.clone(),
+                    on_error: transition_config.on_error.clone(),
                     validations: transition_config.validations.clone().unwrap_or_default(),
                 };
-                state
-                    .transitions
-                    .insert(transition_config.event.clone(), transition);
+                match &transition_config.event {
+                    Some(event) => state
+                        .transitions
+                        .entry(event.clone())
+                        .or_default()
+                        .push(transition),
+                    None => state.eventless.push(transition),
+                }
             }
         }
 
-        // Determine the starting state: use provided initial state or default to the first state
-        let current_state = initial_state.unwrap_or_else(|| config.states[0].name.clone());
+        // Determine the starting state: use provided initial state or default to the first
+        // top-level state, then descend into its initial children to find the active leaves
+        let initial_state = initial_state.unwrap_or_else(|| {
+            config
+                .states
+                .iter()
+                .find(|state| state.parent.is_none())
+                .unwrap_or(&config.states[0])
+                .name
+                .clone()
+        });
+        if !states.contains_key(&initial_state) {
+            return Err(Error::UnknownState(initial_state));
+        }
+        let entered = Self::entry_set(&states, None, &initial_state);
+        let active_states = Self::leaves(&states, &entered);
+        let clock: Arc<dyn Clock> = Arc::new(SystemClock);
+        let timers = Self::schedule_timers(&states, &entered, clock.now_ms());
 
         Ok(StateMachine {
             states: Arc::new(RwLock::new(states)),
-            current_state: Arc::new(RwLock::new(current_state)),
-            action_handler: Arc::new(action_handler),
+            current_state: watch::channel(active_states.clone()).0,
+            active_states: Arc::new(RwLock::new(active_states)),
+            handlers,
+            event_schemas: config.event_schemas.clone(),
+            action_schemas: config.action_schemas.clone(),
+            clock,
+            timers: Arc::new(RwLock::new(timers)),
+            timers_changed: Arc::new(Notify::new()),
+            completed: Arc::new(Notify::new()),
+            config_fingerprint: config.fingerprint,
+            version: Arc::new(AtomicU64::new(0)),
+            timers_restored: false,
+            store: None,
+            journal: None,
+            events: broadcast::channel(EVENT_CHANNEL_CAPACITY).0,
+            max_eventless_steps: DEFAULT_MAX_EVENTLESS_STEPS,
+            template_mode: TemplateMode::default(),
+            validation_mode: ValidationMode::default(),
+            replaying: false,
+            settled: AtomicBool::new(false),
             memory: Arc::new(AsyncRwLock::new(memory)),
             context: Arc::new(AsyncRwLock::new(context)),
             _marker: std::marker::PhantomData,
         })
     }
 
+    /// Creates a state machine from a snapshot taken with [`StateMachine::snapshot`], resuming
+    /// its active states, memory, pending timers and version.
+    ///
+    /// The snapshot must have been taken from the same configuration text, and every state and
+    /// timer it refers to must exist in the configuration. Entry actions are not run again.
+    pub fn restore(
+        config_content: &str,
+        snapshot: Snapshot,
+        handlers: HandlerRegistry<C>,
+        context: C,
+    ) -> Result<Self, Error> {
+        let state_machine = Self::new(config_content, None, handlers, snapshot.memory, context)?;
+        if snapshot.config_hash != state_machine.config_fingerprint {
+            return Err(Error::SnapshotMismatch(format!(
+                "the snapshot was taken from configuration {:016x}, not {:016x}",
+                snapshot.config_hash, state_machine.config_fingerprint
+            )));
+        }
+        {
+            let states_guard = state_machine.states.read().unwrap();
+            if snapshot.current_state.is_empty() {
+                return Err(Error::SnapshotMismatch(
+                    "the snapshot has no active state".to_string(),
+                ));
+            }
+            for leaf in &snapshot.current_state {
+                let state = states_guard
+                    .get(leaf)
+                    .ok_or_else(|| Error::UnknownState(leaf.clone()))?;
+                if !state.children.is_empty() {
+                    return Err(Error::SnapshotMismatch(format!(
+                        "active state '{}' is not a leaf state",
+                        leaf
+                    )));
+                }
+            }
+
+            // The leaves must form a configuration the machine can be in: a single top-level
+            // state, a single child of each active compound state and every region of each
+            // active parallel state
+            let active = Self::active_set(&states_guard, &snapshot.current_state);
+            if Self::leaves(&states_guard, &active).len() != snapshot.current_state.len() {
+                return Err(Error::SnapshotMismatch(
+                    "the snapshot lists an active state twice".to_string(),
+                ));
+            }
+            let top_level = active
+                .iter()
+                .filter(|name| states_guard[*name].parent.is_none())
+                .count();
+            if top_level != 1 {
+                return Err(Error::SnapshotMismatch(format!(
+                    "the snapshot has {} active top-level states",
+                    top_level
+                )));
+            }
+            for name in &active {
+                let state = &states_guard[name];
+                let children = state
+                    .children
+                    .iter()
+                    .filter(|child| active.contains(child))
+                    .count();
+                let expected = if state.parallel {
+                    state.children.len()
+                } else {
+                    1
+                };
+                if !state.children.is_empty() && children != expected {
+                    return Err(Error::SnapshotMismatch(format!(
+                        "state '{}' has {} active child states instead of {}",
+                        name, children, expected
+                    )));
+                }
+            }
+
+            for timer in &snapshot.pending_timers {
+                let state = states_guard
+                    .get(&timer.state)
+                    .ok_or_else(|| Error::UnknownState(timer.state.clone()))?;
+                if !active.contains(&timer.state) {
+                    return Err(Error::SnapshotMismatch(format!(
+                        "the snapshot has a pending timer of inactive state '{}'",
+                        timer.state
+                    )));
+                }
+                if timer.index >= state.after.len() {
+                    return Err(Error::SnapshotMismatch(format!(
+                        "state '{}' has no timed transition #{}",
+                        timer.state, timer.index
+                    )));
+                }
+            }
+        }
+
+        let mut current_state = snapshot.current_state;
+        Self::sort_by_document_order(&state_machine.states.read().unwrap(), &mut current_state);
+        state_machine
+            .current_state
+            .send_replace(current_state.clone());
+        *state_machine.active_states.write().unwrap() = current_state;
+        *state_machine.timers.write().unwrap() = snapshot.pending_timers;
+        state_machine
+            .version
+            .store(snapshot.version, Ordering::SeqCst);
+        Ok(StateMachine {
+            timers_restored: true,
+            ..state_machine
+        })
+    }
+
+    /// Makes sure every action type used by the configuration has a handler, and that no
+    /// handler is registered for a built-in action type.
+    fn check_handlers(
+        config: &StateMachineConfig,
+        handlers: &HandlerRegistry<C>,
+    ) -> Result<(), Error> {
+        // Built-in actions never reach a handler, so one registered for them would be ignored
+        if let Some(action_type) = handlers
+            .action_types()
+            .filter(|action_type| builtins::is_builtin(action_type))
+            .min()
+        {
+            return Err(Error::BuiltinHandler(action_type.to_string()));
+        }
+        match config.actions().find(|action| {
+            !builtins::is_builtin(&action.action_type) && !handlers.handles(&action.action_type)
+        }) {
+            Some(action) => Err(Error::MissingHandler(action.action_type.clone())),
+            None => Ok(()),
+        }
+    }
+
+    /// Computes the hash identifying a configuration text, used as the configuration cache key.
+    /// It only lives as long as the process; snapshots record [`snapshot::config_fingerprint`].
+    fn cache_key(config_content: &str) -> u64 {
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        config_content.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    /// Replaces the clock used to schedule timed transitions. The timers of the active states
+    /// are rescheduled relative to the new clock's current time, except for timers restored
+    /// from a snapshot, which keep their deadlines.
+    pub fn with_clock(self, clock: Arc<dyn Clock>) -> Self {
+        if self.timers_restored {
+            return StateMachine { clock, ..self };
+        }
+        let timers = {
+            let states_guard = self.states.read().unwrap();
+            let active_states_guard = self.active_states.read().unwrap();
+            let active = Self::active_set(&states_guard, &active_states_guard);
+            Self::schedule_timers(&states_guard, &active, clock.now_ms())
+        };
+        *self.timers.write().unwrap() = timers;
+        StateMachine { clock, ..self }
+    }
+
+    /// Saves a snapshot of the machine to `store` under `instance_id` after every step.
+    ///
+    /// Steps are saved with [`StateStore::compare_and_swap`], expecting the version the step
+    /// started from. If another writer advanced the instance first, or the store fails, the
+    /// step is rolled back and [`Error::VersionConflict`] or the store's error is returned.
+    pub fn with_store(self, store: Arc<dyn StateStore>, instance_id: impl Into<String>) -> Self {
+        StateMachine {
+            store: Some((store, instance_id.into())),
+            ..self
+        }
+    }
+
+    /// Records every step in a journal, available from [`StateMachine::journal`]. The journal
+    /// starts from a snapshot of the machine taken before its first step.
+    pub fn with_journal(self) -> Self {
+        StateMachine {
+            journal: Some(Arc::new(RwLock::new(None))),
+            ..self
+        }
+    }
+
+    /// Sets how placeholders referring to missing fields are rendered in action commands and
+    /// parameters. Defaults to [`TemplateMode::Strict`].
+    pub fn with_template_mode(self, template_mode: TemplateMode) -> Self {
+        StateMachine {
+            template_mode,
+            ..self
+        }
+    }
+
+    /// Sets whether validations stop at the first broken rule or report every broken rule.
+    /// Defaults to [`ValidationMode::FailFast`].
+    pub fn with_validation_mode(self, validation_mode: ValidationMode) -> Self {
+        StateMachine {
+            validation_mode,
+            ..self
+        }
+    }
+
+    /// Sets how many consecutive eventless steps a single trigger may take before it fails with
+    /// [`Error::EventlessLoop`]. Defaults to 100.
+    pub fn with_max_eventless_steps(self, max_eventless_steps: usize) -> Self {
+        StateMachine {
+            max_eventless_steps,
+            ..self
+        }
+    }
+
     /// Generates and compiles the JSON schema for the state machine configuration.
-    fn generate_and_compile_schema() -> Result<serde_json::Value, String> {
+    fn generate_and_compile_schema() -> Result<serde_json::Value, Error> {
         // Define the JSON schema as a serde_json::Value
         let schema_json = serde_json::json!({
             "$schema": "http://json-schema.org/draft-07/schema#",
@@ -266,6 +1013,14 @@ impl<C> StateMachine<'_, C> {
             "type": "object",
             "required": ["states", "transitions"],
             "properties": {
+                "event_schemas": {
+                    "type": "object",
+                    "additionalProperties": { "type": ["object", "boolean"] }
+                },
+                "action_schemas": {
+                    "type": "object",
+                    "additionalProperties": { "type": ["object", "boolean"] }
+                },
                 "states": {
                     "type": "array",
                     "items": {
@@ -273,6 +1028,10 @@ impl<C> StateMachine<'_, C> {
                         "required": ["name"],
                         "properties": {
                             "name": { "type": "string" },
+                            "parent": { "type": "string" },
+                            "initial": { "type": "string" },
+                            "parallel": { "type": "boolean", "default": false },
+                            "final": { "type": "boolean", "default": false },
                             "on_enter_actions": {
                                 "type": "array",
                                 "items": { "$ref": "#/definitions/action" },
@@ -286,6 +1045,22 @@ impl<C> StateMachine<'_, C> {
                             "validations": {
                                 "type": "array",
                                 "items": { "$ref": "#/definitions/validation_rule" }
+                            },
+                            "after": {
+                                "type": "array",
+                                "items": {
+                                    "type": "object",
+                                    "required": ["duration_ms", "to"],
+                                    "properties": {
+                                        "duration_ms": { "type": "integer", "minimum": 0 },
+                                        "to": { "type": "string" },
+                                        "actions": {
+                                            "type": "array",
+                                            "items": { "$ref": "#/definitions/action" },
+                                            "default": []
+                                        }
+                                    }
+                                }
                             }
                         }
                     }
@@ -294,11 +1069,14 @@ impl<C> StateMachine<'_, C> {
                     "type": "array",
                     "items": {
                         "type": "object",
-                        "required": ["from", "event", "to"],
+                        "required": ["from", "to"],
                         "properties": {
                             "from": { "type": "string" },
                             "event": { "type": "string" },
                             "to": { "type": "string" },
+                            "guard": { "$ref": "#/definitions/condition" },
+                            "error_policy": { "enum": ["abort", "continue", "redirect"] },
+                            "on_error": { "type": "string" },
                             "actions": {
                                 "type": "array",
                                 "items": { "$ref": "#/definitions/action" },
@@ -318,7 +1096,8 @@ impl<C> StateMachine<'_, C> {
                     "required": ["action_type", "command"],
                     "properties": {
                         "action_type": { "type": "string" },
-                        "command": { "type": "string" }
+                        "command": { "type": "string" },
+                        "params": {}
                     }
                 },
                 "validation_rule": {
@@ -357,7 +1136,12 @@ impl<C> StateMachine<'_, C> {
                             "required": ["type"],
                             "properties": {
                                 "type": { "const": "min_value" },
-                                "value": { "type": "number" }
+                                "value": {
+                                    "oneOf": [
+                                        { "type": "number" },
+                                        { "$ref": "#/definitions/field_reference" }
+                                    ]
+                                }
                             }
                         },
                         {
@@ -365,7 +1149,12 @@ impl<C> StateMachine<'_, C> {
                             "required": ["type"],
                             "properties": {
                                 "type": { "const": "max_value" },
-                                "value": { "type": "number" }
+                                "value": {
+                                    "oneOf": [
+                                        { "type": "number" },
+                                        { "$ref": "#/definitions/field_reference" }
+                                    ]
+                                }
                             }
                         },
                         {
@@ -394,18 +1183,136 @@ impl<C> StateMachine<'_, C> {
                                     "items": {}
                                 }
                             }
+                        },
+                        {
+                            "type": "object",
+                            "required": ["type", "value"],
+                            "properties": {
+                                "type": { "const": "min_length" },
+                                "value": {
+                                    "oneOf": [
+                                        { "type": "integer", "minimum": 0 },
+                                        { "$ref": "#/definitions/field_reference" }
+                                    ]
+                                }
+                            }
+                        },
+                        {
+                            "type": "object",
+                            "required": ["type", "value"],
+                            "properties": {
+                                "type": { "const": "max_length" },
+                                "value": {
+                                    "oneOf": [
+                                        { "type": "integer", "minimum": 0 },
+                                        { "$ref": "#/definitions/field_reference" }
+                                    ]
+                                }
+                            }
+                        },
+                        {
+                            "type": "object",
+                            "required": ["type", "pattern"],
+                            "properties": {
+                                "type": { "const": "pattern" },
+                                "pattern": { "type": "string" }
+                            }
+                        },
+                        {
+                            "type": "object",
+                            "required": ["type", "format"],
+                            "properties": {
+                                "type": { "const": "format" },
+                                "format": { "enum": ["email", "uuid", "date-time"] }
+                            }
+                        },
+                        {
+                            "type": "object",
+                            "required": ["type", "value"],
+                            "properties": {
+                                "type": { "const": "contains" },
+                                "value": {}
+                            }
+                        },
+                        {
+                            "type": "object",
+                            "required": ["type", "is_unique"],
+                            "properties": {
+                                "type": { "const": "unique_items" },
+                                "is_unique": { "type": "boolean" }
+                            }
+                        },
+                        {
+                            "type": "object",
+                            "required": ["type", "expr"],
+                            "properties": {
+                                "type": { "const": "expr" },
+                                "expr": { "type": "string", "minLength": 1 }
+                            }
                         }
                         // Add more field rule schemas as needed
                     ]
                 },
-                "condition": {
+                "field_reference": {
                     "type": "object",
-                    "required": ["field", "operator", "value"],
+                    "required": ["$ref_field"],
                     "properties": {
-                        "field": { "type": "string" },
-                        "operator": { "type": "string" },
-                        "value": {}
-                    }
+                        "$ref_field": { "type": "string" }
+                    },
+                    "additionalProperties": false
+                },
+                "condition": {
+                    "oneOf": [
+                        {
+                            "type": "object",
+                            "required": ["field", "operator"],
+                            "properties": {
+                                "field": { "type": "string" },
+                                "operator": {
+                                    "enum": [
+                                        "==", "!=", ">", "<", ">=", "<=", "in", "not_in",
+                                        "contains", "exists", "missing", "matches",
+                                        "starts_with", "ends_with"
+                                    ]
+                                },
+                                "value": {}
+                            },
+                            "additionalProperties": false
+                        },
+                        {
+                            "type": "object",
+                            "required": ["all"],
+                            "properties": {
+                                "all": {
+                                    "type": "array",
+                                    "minItems": 1,
+                                    "items": { "$ref": "#/definitions/condition" }
+                                }
+                            },
+                            "additionalProperties": false
+                        },
+                        {
+                            "type": "object",
+                            "required": ["any"],
+                            "properties": {
+                                "any": {
+                                    "type": "array",
+                                    "minItems": 1,
+                                    "items": { "$ref": "#/definitions/condition" }
+                                }
+                            },
+                            "additionalProperties": false
+                        },
+                        {
+                            "type": "object",
+                            "required": ["not"],
+                            "properties": {
+                                "not": { "$ref": "#/definitions/condition" }
+                            },
+                            "additionalProperties": false
+                        },
+                        { "type": "string", "minLength": 1 }
+                    ]
                 }
             }
         });
@@ -420,306 +1327,1754 @@ impl<C> StateMachine<'_, C> {
             .map(|config| Action {
                 action_type: config.action_type.clone(),
                 command: config.command.clone(),
+                params: config.params.clone(),
             })
             .collect()
     }
 
     /// Validates the state machine configuration.
-    fn validate_config(config: &StateMachineConfig) -> Result<(), String> {
+    fn validate_config(config: &StateMachineConfig) -> Result<(), Error> {
         if config.states.is_empty() {
-            return Err("State machine must have at least one state.".into());
+            return Err(Error::InvalidConfig(
+                "State machine must have at least one state.".into(),
+            ));
         }
 
         let mut state_set = std::collections::HashSet::new();
         for state in &config.states {
             if !state_set.insert(&state.name) {
-                return Err(format!("Duplicate state found: {}", state.name));
+                return Err(Error::InvalidConfig(format!(
+                    "Duplicate state found: {}",
+                    state.name
+                )));
             }
         }
 
-        for transition in &config.transitions {
-            if !config.states.iter().any(|s| s.name == transition.from) {
-                return Err(format!(
-                    "Transition 'from' state '{}' is not defined in the states list.",
-                    transition.from
-                ));
-            }
+        for state in &config.states {
+            for after in &state.after {
+                if !state_set.contains(&after.to) {
+                    return Err(Error::UnknownState(after.to.clone()));
+                }
+            }
+            if let Some(parent) = &state.parent {
+                if !state_set.contains(parent) {
+                    return Err(Error::UnknownState(parent.clone()));
+                }
+            }
+            if state.is_final {
+                if config
+                    .states
+                    .iter()
+                    .any(|s| s.parent.as_ref() == Some(&state.name))
+                {
+                    return Err(Error::InvalidConfig(format!(
+                        "Final state '{}' cannot have child states.",
+                        state.name
+                    )));
+                }
+                if !state.after.is_empty()
+                    || config.transitions.iter().any(|t| t.from == state.name)
+                {
+                    return Err(Error::InvalidConfig(format!(
+                        "Final state '{}' cannot have outgoing transitions.",
+                        state.name
+                    )));
+                }
+            }
+            if state.parallel {
+                if state.initial.is_some() {
+                    return Err(Error::InvalidConfig(format!(
+                        "Parallel state '{}' cannot declare an initial state.",
+                        state.name
+                    )));
+                }
+                if !config
+                    .states
+                    .iter()
+                    .any(|s| s.parent.as_ref() == Some(&state.name))
+                {
+                    return Err(Error::InvalidConfig(format!(
+                        "Parallel state '{}' must have at least one child region.",
+                        state.name
+                    )));
+                }
+            }
+            if let Some(initial) = &state.initial {
+                if !config
+                    .states
+                    .iter()
+                    .any(|s| s.name == *initial && s.parent.as_ref() == Some(&state.name))
+                {
+                    return Err(Error::InvalidConfig(format!(
+                        "Initial state '{}' of '{}' is not one of its child states.",
+                        initial, state.name
+                    )));
+                }
+            }
+
+            // Walk up the parent chain to make sure the hierarchy has no cycles
+            let mut visited = std::collections::HashSet::new();
+            let mut cursor = Some(state);
+            while let Some(current) = cursor {
+                if !visited.insert(&current.name) {
+                    return Err(Error::InvalidConfig(format!(
+                        "State '{}' is part of a parent cycle.",
+                        state.name
+                    )));
+                }
+                cursor = current
+                    .parent
+                    .as_ref()
+                    .and_then(|parent| config.states.iter().find(|s| s.name == *parent));
+            }
+        }
+
+        for transition in &config.transitions {
+            if !config.states.iter().any(|s| s.name == transition.from) {
+                return Err(Error::UnknownState(transition.from.clone()));
+            }
             if !config.states.iter().any(|s| s.name == transition.to) {
-                return Err(format!(
-                    "Transition 'to' state '{}' is not defined in the states list.",
-                    transition.to
-                ));
+                return Err(Error::UnknownState(transition.to.clone()));
             }
-            if transition.event.trim().is_empty() {
-                return Err(format!(
+            if transition
+                .event
+                .as_ref()
+                .is_some_and(|event| event.trim().is_empty())
+            {
+                return Err(Error::InvalidConfig(format!(
                     "Transition from '{}' to '{}' has an empty event.",
                     transition.from, transition.to
-                ));
+                )));
+            }
+            match (&transition.error_policy, &transition.on_error) {
+                (ErrorPolicy::Redirect, Some(on_error)) => {
+                    if !config.states.iter().any(|s| s.name == *on_error) {
+                        return Err(Error::UnknownState(on_error.clone()));
+                    }
+                }
+                (ErrorPolicy::Redirect, None) => {
+                    return Err(Error::InvalidConfig(format!(
+                        "Transition from '{}' on '{}' uses the 'redirect' error policy without an 'on_error' state.",
+                        transition.from, transition.event_label()
+                    )));
+                }
+                (_, Some(_)) => {
+                    return Err(Error::InvalidConfig(format!(
+                        "Transition from '{}' on '{}' declares an 'on_error' state but its error policy is not 'redirect'.",
+                        transition.from, transition.event_label()
+                    )));
+                }
+                (_, None) => {}
+            }
+        }
+
+        // Candidates for the same event are tried in declaration order, so anything declared
+        // after an unguarded transition could never be taken
+        let mut unguarded = std::collections::HashSet::new();
+        for transition in &config.transitions {
+            let key = (&transition.from, &transition.event);
+            if unguarded.contains(&key) {
+                return Err(Error::InvalidConfig(format!(
+                    "Duplicate transition for event '{}' from state '{}': an earlier transition without a guard always wins.",
+                    transition.event_label(), transition.from
+                )));
+            }
+            if transition.guard.is_none() {
+                unguarded.insert(key);
+            }
+        }
+
+        // Check the templates and the parameters of every action against the schema of its type.
+        // Parameters with placeholders are only checked once rendered, before the action runs
+        for action in config.actions() {
+            if let Err(message) = Template::parse(&action.command)
+                .map(|_| ())
+                .and_then(|_| template::parse_value(&action.params))
+            {
+                return Err(Error::InvalidConfig(format!(
+                    "Malformed template in action '{}': {}",
+                    action.action_type, message
+                )));
+            }
+            if builtins::is_builtin(&action.action_type) {
+                if let Err(message) =
+                    builtins::check(&action.action_type, &action.command, &action.params)
+                {
+                    return Err(Error::InvalidConfig(format!(
+                        "Invalid built-in action '{}' on '{}': {}",
+                        action.action_type, action.command, message
+                    )));
+                }
+            }
+            if let Some(schema) = config.action_schemas.get(&action.action_type) {
+                if template::has_placeholders(&action.params) {
+                    continue;
+                }
+                if let Err(error) = schema.validator.validate(&action.params) {
+                    return Err(Error::InvalidConfig(format!(
+                        "Invalid params for action '{}' with command '{}': {}",
+                        action.action_type, action.command, error
+                    )));
+                }
             }
         }
 
         Ok(())
     }
 
-    /// Triggers an event, causing a state transition if applicable and executing actions.
-    pub async fn trigger(&self, event: &str) -> Result<(), String> {
-        // Acquire a read lock on the current state and clone its value
-        let current_state_name = {
-            let current_state_guard = self.current_state.read().unwrap();
-            current_state_guard.clone()
+    /// Triggers an event without a payload. See [`StateMachine::trigger_with`].
+    pub async fn trigger(&self, event: &str) -> Result<(), Error> {
+        self.trigger_with(event, Value::Null).await
+    }
+
+    /// Triggers an event carrying a payload, causing a state transition if applicable and
+    /// executing actions.
+    ///
+    /// The payload is validated against the event's schema from `event_schemas`, if any. Guards
+    /// and validations can refer to payload fields with the `event.` prefix (e.g.
+    /// `event.amount`), and the payload is passed to the action handler.
+    ///
+    /// When an action fails, the transition's `error_policy` decides what happens: `abort`
+    /// (the default) restores the memory and active states from before the event, `continue`
+    /// logs the failure and carries on, and `redirect` moves to the transition's `on_error`
+    /// state instead of its target. The action error is returned for `abort` and `redirect`.
+    /// The context is never rolled back.
+    ///
+    /// The event is offered to every active leaf state. For each leaf, the transition is looked
+    /// up on the leaf first and then on each of its ancestors, and the first candidate whose
+    /// guard holds (or that has no guard) is taken. When a parallel state has several
+    /// active regions, every region may take a transition for the same event, as long as the
+    /// selected transitions do not exit the same states. Exit actions run from the active
+    /// leaves up to (but excluding) the least common ancestor of the source and target states,
+    /// and entry actions run from there back down to the target's initial leaves.
+    ///
+    /// Once the event's transitions are done, transitions declared without an `event` are
+    /// followed for as long as one is enabled, with the same payload. Each round of eventless
+    /// transitions is its own step, so an aborted round does not undo the previous ones. More
+    /// than [`StateMachine::with_max_eventless_steps`] rounds fail with
+    /// [`Error::EventlessLoop`].
+    pub async fn trigger_with(&self, event: &str, payload: Value) -> Result<(), Error> {
+        // Acquire write locks on memory and context first, so that steps run one at a time and
+        // the active states read below cannot be changed by a step still in progress
+        let mut memory = self.memory.write().await;
+        let mut context = self.context.write().await;
+
+        let before = self.store.as_ref().map(|_| self.capture(&memory));
+        self.start_journal(&memory);
+        if let Err(error) = self.settle_initial_states(&mut memory, &mut context).await {
+            self.persist(before, &mut memory).await?;
+            return Err(error);
+        }
+
+        // Acquire a read lock on the active states and clone their value
+        let active_leaves = {
+            let active_states_guard = self.active_states.read().unwrap();
+            active_states_guard.clone()
         }; // Lock is released here
 
-        // Acquire a read lock on the states and get the current state and transition
-        let (current_state, transition) = {
-            let states_guard = self.states.read().unwrap();
-            // Clone the current state to own its data
-            let current_state = states_guard.get(&current_state_name).cloned();
-            if let Some(current_state) = current_state {
-                // Clone the transition to own its data
-                if let Some(transition) = current_state.transitions.get(event).cloned() {
-                    (current_state, transition)
-                } else {
-                    return Err(format!(
-                        "No transition found for event '{}' from state '{}'.",
-                        event, current_state_name
-                    ));
+        let trigger = JournalTrigger::Event {
+            name: event.to_string(),
+            payload: payload.clone(),
+        };
+        let microsteps =
+            match self.select_event_transitions(event, &payload, &active_leaves, &memory) {
+                Ok(microsteps) => microsteps,
+                Err(error) => {
+                    self.publish(&trigger, &active_leaves, Err(&error));
+                    self.persist(before, &mut memory).await?;
+                    return Err(error);
                 }
-            } else {
-                return Err(format!(
-                    "Current state '{}' not found in state machine.",
-                    current_state_name
-                ));
+            };
+
+        let result = match self
+            .execute_microsteps(
+                &microsteps,
+                active_leaves.clone(),
+                &mut memory,
+                &mut context,
+                &payload,
+                trigger.clone(),
+            )
+            .await
+        {
+            Ok(()) => {
+                self.follow_eventless_transitions(&mut memory, &mut context, &payload)
+                    .await
             }
-        }; // Lock is released here
+            Err(error) => Err(error),
+        };
+        if let Err(error) = self.persist(before, &mut memory).await {
+            self.publish(&trigger, &active_leaves, Err(&error));
+            return Err(error);
+        }
+        result
+    }
 
-        // Now `current_state` and `transition` own their data and do not borrow from `states_guard`
+    /// Validates the payload of an event and selects the transitions it enables, failing if
+    /// there are none.
+    fn select_event_transitions(
+        &self,
+        event: &str,
+        payload: &Value,
+        active_leaves: &[String],
+        memory: &Map<String, Value>,
+    ) -> Result<Vec<Microstep>, Error> {
+        // Validate the payload before anything else happens
+        if let Some(schema) = self.event_schemas.get(event) {
+            if let Err(error) = schema.validator.validate(payload) {
+                return Err(Error::InvalidPayload {
+                    event: event.to_string(),
+                    message: error.to_string(),
+                });
+            }
+        }
 
-        // Acquire write locks on memory and context
+        // Acquire a read lock on the states and select the transitions to take, along with the
+        // states that are exited and entered by each of them
+        let microsteps = {
+            let states_guard = self.states.read().unwrap();
+            Self::select_transitions(&states_guard, active_leaves, Some(event), memory, payload)?
+        }; // Lock is released here
+        if microsteps.is_empty() {
+            return Err(Error::NoTransition {
+                event: event.to_string(),
+                state: active_leaves.join(", "),
+            });
+        }
+        Ok(microsteps)
+    }
+
+    /// Takes the eventless transitions enabled in the current states until the machine settles.
+    ///
+    /// Every step already does this after its transitions, so this is only needed to leave a
+    /// routing state the machine was created or restored in without waiting for the first
+    /// event or timer, which settle it too.
+    pub async fn settle(&self) -> Result<(), Error> {
         let mut memory = self.memory.write().await;
         let mut context = self.context.write().await;
+        let before = self.store.as_ref().map(|_| self.capture(&memory));
+        self.start_journal(&memory);
+        self.settled.store(true, Ordering::SeqCst);
+        let result = self
+            .follow_eventless_transitions(&mut memory, &mut context, &Value::Null)
+            .await;
+        self.persist(before, &mut memory).await?;
+        result
+    }
+
+    /// Follows the eventless transitions of the states the machine was created or restored in,
+    /// before its first step.
+    async fn settle_initial_states(
+        &self,
+        memory: &mut Map<String, Value>,
+        context: &mut C,
+    ) -> Result<(), Error> {
+        if self.settled.swap(true, Ordering::SeqCst) {
+            return Ok(());
+        }
+        self.follow_eventless_transitions(memory, context, &Value::Null)
+            .await
+    }
 
-        // Execute state validations
-        Self::evaluate_validations(&current_state.validations, &memory)?;
+    /// Fires every timed transition that is due according to the clock, earliest first, and
+    /// returns how many were fired.
+    ///
+    /// Each timer fires as its own step, with the same validations and error policies as an
+    /// event. A timer is consumed even when its transition fails, and the first failure is
+    /// returned.
+    pub async fn fire_due_timers(&self) -> Result<usize, Error> {
+        let mut fired = 0;
+        loop {
+            // Acquire write locks on memory and context
+            let mut memory = self.memory.write().await;
+            let mut context = self.context.write().await;
+            let before = self.store.as_ref().map(|_| self.capture(&memory));
+            self.start_journal(&memory);
+            if let Err(error) = self.settle_initial_states(&mut memory, &mut context).await {
+                self.persist(before, &mut memory).await?;
+                return Err(error);
+            }
+
+            let now = self.clock.now_ms();
+            let due_timer = {
+                let mut timers_guard = self.timers.write().unwrap();
+                let earliest = timers_guard
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, timer)| timer.due_at_ms <= now)
+                    .min_by_key(|(_, timer)| timer.due_at_ms)
+                    .map(|(index, _)| index);
+                earliest.map(|index| timers_guard.remove(index))
+            }; // Lock is released here
+            let Some(timer) = due_timer else {
+                return Ok(fired);
+            };
 
-        // Execute transition validations
-        Self::evaluate_validations(&transition.validations, &memory)?;
+            let active_leaves = self.active_states.read().unwrap().clone();
+            let microstep = {
+                let states_guard = self.states.read().unwrap();
+                Self::timer_microstep(&states_guard, &active_leaves, &timer.state, timer.index)?
+            }; // Lock is released here
 
-        // Execute on-exit actions
-        self.execute_actions(&current_state.on_exit_actions, &mut memory, &mut context)
-            .await;
+            let trigger = JournalTrigger::Timer {
+                state: timer.state,
+                index: timer.index,
+            };
+            let result = match self
+                .execute_microsteps(
+                    &[microstep],
+                    active_leaves.clone(),
+                    &mut memory,
+                    &mut context,
+                    &Value::Null,
+                    trigger.clone(),
+                )
+                .await
+            {
+                Ok(()) => {
+                    self.follow_eventless_transitions(&mut memory, &mut context, &Value::Null)
+                        .await
+                }
+                Err(error) => Err(error),
+            };
+            if let Err(error) = self.persist(before, &mut memory).await {
+                self.publish(&trigger, &active_leaves, Err(&error));
+                return Err(error);
+            }
+            result?;
+            fired += 1;
+        }
+    }
+
+    /// Returns when the next timed transition is due, in milliseconds since the Unix epoch.
+    pub fn next_timer_deadline(&self) -> Option<u64> {
+        let timers_guard = self.timers.read().unwrap();
+        timers_guard.iter().map(|timer| timer.due_at_ms).min()
+    }
+
+    /// Fires timed transitions as they become due, forever.
+    ///
+    /// This sleeps in real time until the next deadline, so it is meant to be spawned alongside
+    /// a machine using the [`SystemClock`]. Failures are logged. With a [`ManualClock`], call
+    /// [`StateMachine::fire_due_timers`] after advancing the clock instead.
+    pub async fn run_timers(&self) {
+        loop {
+            let timers_changed = self.timers_changed.notified();
+            match self.next_timer_deadline() {
+                Some(deadline) => {
+                    let wait = deadline.saturating_sub(self.clock.now_ms());
+                    tokio::select! {
+                        _ = tokio::time::sleep(std::time::Duration::from_millis(wait)) => {
+                            if let Err(error) = self.fire_due_timers().await {
+                                log::warn!("Timed transition failed: {}", error);
+                            }
+                        }
+                        _ = timers_changed => {}
+                    }
+                }
+                None => timers_changed.await,
+            }
+        }
+    }
+
+    /// Saves the machine to its store if the step that started from `before` changed it. If the
+    /// store rejects or fails to save the snapshot, the whole step is rolled back.
+    ///
+    /// Stores do blocking I/O, so the save runs on tokio's blocking thread pool rather than on
+    /// the runtime thread driving the step.
+    async fn persist(
+        &self,
+        before: Option<Snapshot>,
+        memory: &mut Map<String, Value>,
+    ) -> Result<(), Error> {
+        let (Some((store, instance_id)), Some(before)) = (&self.store, before) else {
+            return Ok(());
+        };
+        let after = self.capture(memory);
+        if after.version == before.version {
+            return Ok(());
+        }
+
+        let (store, id, expected_version) = (store.clone(), instance_id.clone(), before.version);
+        let saved = tokio::task::spawn_blocking(move || {
+            store.compare_and_swap(&id, expected_version, &after)
+        })
+        .await
+        .unwrap_or_else(|error| Err(Error::Store(format!("Failed to save: {}", error))));
+        let error = match saved {
+            Ok(true) => return Ok(()),
+            Ok(false) => Error::VersionConflict {
+                instance_id: instance_id.clone(),
+                expected_version: before.version,
+            },
+            Err(error) => error,
+        };
+        *memory = before.memory;
+        *self.active_states.write().unwrap() = before.current_state;
+        *self.timers.write().unwrap() = before.pending_timers;
+        self.version.store(before.version, Ordering::SeqCst);
+        if let Some(journal) = self.journal.as_ref() {
+            if let Some(journal) = journal.write().unwrap().as_mut() {
+                journal
+                    .entries
+                    .retain(|entry| entry.version <= before.version);
+            }
+        }
+        self.timers_changed.notify_waiters();
+        Err(error)
+    }
+
+    /// Takes eventless transitions until the machine settles, each round being a step of its
+    /// own: a failing round is handled by its error policy without undoing earlier rounds or
+    /// the transition that led to it.
+    async fn follow_eventless_transitions(
+        &self,
+        memory: &mut Map<String, Value>,
+        context: &mut C,
+        payload: &Value,
+    ) -> Result<(), Error> {
+        for step in 0..=self.max_eventless_steps {
+            let active_leaves = self.active_states.read().unwrap().clone();
+            let selected = {
+                let states_guard = self.states.read().unwrap();
+                Self::select_transitions(&states_guard, &active_leaves, None, memory, payload)
+            }; // Lock is released here
+            let error = match selected {
+                Ok(microsteps) if microsteps.is_empty() => return Ok(()),
+                Ok(_) if step == self.max_eventless_steps => Error::EventlessLoop {
+                    state: active_leaves.join(", "),
+                    limit: self.max_eventless_steps,
+                },
+                Ok(microsteps) => {
+                    self.execute_microsteps(
+                        &microsteps,
+                        active_leaves,
+                        memory,
+                        context,
+                        payload,
+                        JournalTrigger::Eventless,
+                    )
+                    .await?;
+                    continue;
+                }
+                Err(error) => error,
+            };
+            self.publish(&JournalTrigger::Eventless, &active_leaves, Err(&error));
+            return Err(error);
+        }
+        Ok(())
+    }
+
+    /// Runs the selected microsteps after checking their validations, applying the error
+    /// policy of the failing transition if an action fails.
+    async fn execute_microsteps(
+        &self,
+        microsteps: &[Microstep],
+        active_leaves: Vec<String>,
+        memory: &mut Map<String, Value>,
+        context: &mut C,
+        payload: &Value,
+        trigger: JournalTrigger,
+    ) -> Result<(), Error> {
+        // Execute the state validations of every state being exited and the transition
+        // validations before any action runs, and collect the fields the actions must not change
+        let mode = self.validation_mode;
+        let mut violations = Vec::new();
+        let mut validation = microsteps.iter().try_for_each(|microstep| {
+            for state in &microstep.exit_states {
+                Self::evaluate_validations(
+                    &state.validations,
+                    memory,
+                    payload,
+                    mode,
+                    &mut violations,
+                )?;
+            }
+            Self::evaluate_validations(
+                &microstep.transition.validations,
+                memory,
+                payload,
+                mode,
+                &mut violations,
+            )
+        });
+        if validation.is_ok() && !violations.is_empty() {
+            validation = Err(Error::Validations(violations));
+        }
+        let validation = validation.and_then(|()| {
+            self.collect_protected_fields(microsteps, &active_leaves, memory, payload)
+        });
+        let protected = match validation {
+            Ok(protected) => protected,
+            Err(error) => {
+                self.publish(&trigger, &active_leaves, Err(&error));
+                return Err(error);
+            }
+        };
+
+        // Keep a snapshot of the memory and timers so that a failed action can roll back
+        let memory_snapshot = memory.clone();
+        let timers_snapshot = self.timers.read().unwrap().clone();
+        let mut active = {
+            let states_guard = self.states.read().unwrap();
+            Self::active_set(&states_guard, &active_leaves)
+        };
+
+        for (microstep, protected) in microsteps.iter().zip(&protected) {
+            let protected_values: Vec<Vec<Option<Value>>> = protected
+                .iter()
+                .map(|protected| Self::protected_values(&protected.field, memory))
+                .collect();
+            let result = self
+                .run_microstep(microstep, &mut active, memory, context, payload)
+                .await;
+            // Only an action failure is redirected; changing a read-only field always aborts,
+            // even if an action failed after changing it
+            let protection =
+                Self::check_protected_fields(protected, &protected_values, memory, mode);
+            let redirect = match (&result, &protection, &microstep.transition.error_policy) {
+                (Err(_), Ok(()), ErrorPolicy::Redirect) => microstep.transition.on_error.as_ref(),
+                _ => None,
+            };
+            let result = protection.and(result);
+            if let Err(error) = result {
+                if let Some(on_error) = redirect {
+                    self.redirect(microstep, on_error, &mut active, memory, context, payload)
+                        .await;
+                    self.version.fetch_add(1, Ordering::SeqCst);
+                    self.record_step(
+                        &trigger,
+                        &active_leaves,
+                        microsteps,
+                        &memory_snapshot,
+                        memory,
+                        Some(&error),
+                    );
+                } else {
+                    *memory = memory_snapshot;
+                    *self.active_states.write().unwrap() = active_leaves.clone();
+                    *self.timers.write().unwrap() = timers_snapshot;
+                    self.timers_changed.notify_waiters();
+                }
+                self.publish(&trigger, &active_leaves, Err(&error));
+                return Err(error);
+            }
+        }
+
+        self.version.fetch_add(1, Ordering::SeqCst);
+        self.record_step(
+            &trigger,
+            &active_leaves,
+            microsteps,
+            &memory_snapshot,
+            memory,
+            None,
+        );
+        self.publish(&trigger, &active_leaves, Ok(()));
+        Ok(())
+    }
+
+    /// Publishes a step to subscribers and the current active states to watchers.
+    fn publish(&self, trigger: &JournalTrigger, from: &[String], outcome: Result<(), &Error>) {
+        let to = self.active_states.read().unwrap().clone();
+        if self.events.receiver_count() > 0 {
+            // Sending only fails when every subscriber has gone away in the meantime
+            let _ = self.events.send(TransitionEvent {
+                trigger: trigger.clone(),
+                from: from.to_vec(),
+                to: to.clone(),
+                timestamp_ms: self.clock.now_ms(),
+                outcome: outcome.map_err(Clone::clone),
+            });
+        }
+        self.current_state.send_if_modified(|current| {
+            if *current == to {
+                return false;
+            }
+            *current = to;
+            true
+        });
+    }
+
+    /// Starts the journal from the current state of the machine if journaling is enabled and
+    /// no step has been recorded yet.
+    fn start_journal(&self, memory: &Map<String, Value>) {
+        if let Some(journal) = &self.journal {
+            let mut journal_guard = journal.write().unwrap();
+            if journal_guard.is_none() {
+                *journal_guard = Some(Journal {
+                    start: self.capture(memory),
+                    entries: Vec::new(),
+                });
+            }
+        }
+    }
+
+    /// Appends a completed step to the journal, if journaling is enabled.
+    fn record_step(
+        &self,
+        trigger: &JournalTrigger,
+        from: &[String],
+        microsteps: &[Microstep],
+        memory_before: &Map<String, Value>,
+        memory_after: &Map<String, Value>,
+        error: Option<&Error>,
+    ) {
+        let Some(journal) = &self.journal else {
+            return;
+        };
+        let actions = microsteps
+            .iter()
+            .flat_map(|microstep| {
+                let exit_actions = microstep
+                    .exit_states
+                    .iter()
+                    .flat_map(|state| &state.on_exit_actions);
+                let entry_actions = microstep
+                    .entry_states
+                    .iter()
+                    .flat_map(|state| &state.on_enter_actions);
+                exit_actions
+                    .chain(&microstep.transition.actions)
+                    .chain(entry_actions)
+            })
+            .cloned()
+            .collect();
+        let entry = JournalEntry {
+            version: self.version.load(Ordering::SeqCst),
+            timestamp_ms: self.clock.now_ms(),
+            trigger: trigger.clone(),
+            from: from.to_vec(),
+            to: self.active_states.read().unwrap().clone(),
+            memory_diff: MemoryDiff::between(memory_before, memory_after),
+            actions,
+            error: error.map(|error| error.to_string()),
+        };
+        if let Some(journal) = journal.write().unwrap().as_mut() {
+            journal.entries.push(entry);
+        }
+    }
+
+    /// Runs the exit, transition and entry actions of a microstep, updating the active states.
+    async fn run_microstep(
+        &self,
+        microstep: &Microstep,
+        active: &mut Vec<String>,
+        memory: &mut Map<String, Value>,
+        context: &mut C,
+        payload: &Value,
+    ) -> Result<(), Error> {
+        let policy = &microstep.transition.error_policy;
+
+        // Execute on-exit actions, innermost state first
+        for state in &microstep.exit_states {
+            self.execute_actions(&state.on_exit_actions, memory, context, payload, policy)
+                .await?;
+        }
 
         // Execute transition actions
-        self.execute_actions(&transition.actions, &mut memory, &mut context)
-            .await;
+        self.execute_actions(
+            &microstep.transition.actions,
+            memory,
+            context,
+            payload,
+            policy,
+        )
+        .await?;
 
-        // Update the current state
-        {
-            let mut current_state_guard = self.current_state.write().unwrap();
-            *current_state_guard = transition.to_state.clone();
-        } // Lock is released here
+        // Update the active states
+        self.update_active(active, &microstep.exit_states, &microstep.entry_states);
+
+        // Execute on-enter actions, outermost state first
+        for state in &microstep.entry_states {
+            self.execute_actions(&state.on_enter_actions, memory, context, payload, policy)
+                .await?;
+        }
+
+        Ok(())
+    }
 
-        // Execute on-enter actions of the next state
-        let next_state_on_enter_actions = {
+    /// Moves to the `on_error` state of a microstep whose actions failed. The states the
+    /// microstep had started exiting are considered exited; any other state that has to be left
+    /// to reach `target` is exited normally. Failures of the actions run here are only logged.
+    async fn redirect(
+        &self,
+        microstep: &Microstep,
+        target: &str,
+        active: &mut Vec<String>,
+        memory: &mut Map<String, Value>,
+        context: &mut C,
+        payload: &Value,
+    ) {
+        active.retain(|name| !microstep.exit_states.iter().any(|s| s.name == *name));
+        let (exit_states, entry_states) = {
             let states_guard = self.states.read().unwrap();
-            if let Some(next_state) = states_guard.get(&transition.to_state) {
-                next_state.on_enter_actions.clone()
+            let domain = Self::transition_domain(&states_guard, &microstep.source, target);
+            let exit_states: Vec<State> = active
+                .iter()
+                .rev()
+                .filter(|name| Self::is_descendant(&states_guard, name, domain.as_deref()))
+                .filter_map(|name| states_guard.get(name).cloned())
+                .collect();
+            let entry_states: Vec<State> =
+                Self::entry_set(&states_guard, domain.as_deref(), target)
+                    .into_iter()
+                    .filter_map(|name| states_guard.get(&name).cloned())
+                    .collect();
+            (exit_states, entry_states)
+        }; // Lock is released here
+
+        let policy = ErrorPolicy::Continue;
+        for state in &exit_states {
+            let _ = self
+                .execute_actions(&state.on_exit_actions, memory, context, payload, &policy)
+                .await;
+        }
+        self.update_active(active, &exit_states, &entry_states);
+        for state in &entry_states {
+            let _ = self
+                .execute_actions(&state.on_enter_actions, memory, context, payload, &policy)
+                .await;
+        }
+    }
+
+    /// Removes the exited states from and adds the entered states to the full set of active
+    /// states, then publishes the resulting active leaves. Timers of exited states are
+    /// cancelled and timers of entered states are scheduled.
+    fn update_active(&self, active: &mut Vec<String>, exited: &[State], entered: &[State]) {
+        let states_guard = self.states.read().unwrap();
+        active.retain(|name| !exited.iter().any(|s| s.name == *name));
+        for state in entered {
+            if !active.contains(&state.name) {
+                active.push(state.name.clone());
+            }
+        }
+        Self::sort_by_document_order(&states_guard, active);
+        let leaves = Self::leaves(&states_guard, active);
+        if Self::all_final(&states_guard, &leaves) {
+            self.completed.notify_waiters();
+        }
+        *self.active_states.write().unwrap() = leaves;
+
+        let entered_names: Vec<String> = entered.iter().map(|s| s.name.clone()).collect();
+        let mut timers_guard = self.timers.write().unwrap();
+        timers_guard.retain(|timer| !exited.iter().any(|s| s.name == timer.state));
+        timers_guard.extend(Self::schedule_timers(
+            &states_guard,
+            &entered_names,
+            self.clock.now_ms(),
+        ));
+        self.timers_changed.notify_waiters();
+    }
+
+    /// Returns the timers to schedule when the given states are entered at time `now_ms`.
+    fn schedule_timers(
+        states: &HashMap<String, State>,
+        entered: &[String],
+        now_ms: u64,
+    ) -> Vec<PendingTimer> {
+        entered
+            .iter()
+            .filter_map(|name| states.get(name))
+            .flat_map(|state| {
+                state
+                    .after
+                    .iter()
+                    .enumerate()
+                    .map(move |(index, after)| PendingTimer {
+                        state: state.name.clone(),
+                        index,
+                        due_at_ms: now_ms.saturating_add(after.delay_ms),
+                    })
+            })
+            .collect()
+    }
+
+    /// Selects the transitions enabled by an event in the given active configuration, or the
+    /// enabled eventless transitions when `event` is `None`.
+    ///
+    /// Leaves are visited in document order and the first transition found for each leaf wins.
+    /// A transition is skipped when it would exit a state already exited by a previously
+    /// selected transition, which also removes duplicates found through a shared ancestor.
+    fn select_transitions(
+        states: &HashMap<String, State>,
+        active_leaves: &[String],
+        event: Option<&str>,
+        memory: &Map<String, Value>,
+        payload: &Value,
+    ) -> Result<Vec<Microstep>, Error> {
+        let active = Self::active_set(states, active_leaves);
+        let mut exited = std::collections::HashSet::new();
+        let mut microsteps = Vec::new();
+        for leaf in active_leaves {
+            if !states.contains_key(leaf) {
+                return Err(Error::UnknownState(leaf.clone()));
+            }
+            let Some((source, transition)) =
+                Self::find_transition(states, leaf, event, memory, payload)?
+            else {
+                continue;
+            };
+            let microstep = Self::build_microstep(states, &active, source, transition);
+            if microstep
+                .exit_states
+                .iter()
+                .any(|state| exited.contains(&state.name))
+            {
+                continue;
+            }
+            exited.extend(microstep.exit_states.iter().map(|state| state.name.clone()));
+            microsteps.push(microstep);
+        }
+        Ok(microsteps)
+    }
+
+    /// Builds the microstep of the timed transition at `index` in the `after` list of `state`.
+    fn timer_microstep(
+        states: &HashMap<String, State>,
+        active_leaves: &[String],
+        state: &str,
+        index: usize,
+    ) -> Result<Microstep, Error> {
+        let transition = states
+            .get(state)
+            .and_then(|s| s.after.get(index))
+            .map(|after| after.transition.clone())
+            .ok_or_else(|| Error::UnknownState(state.to_string()))?;
+        let active = Self::active_set(states, active_leaves);
+        Ok(Self::build_microstep(
+            states,
+            &active,
+            state.to_string(),
+            transition,
+        ))
+    }
+
+    /// Computes the states exited and entered when `transition`, declared on `source`, is taken
+    /// from the given set of active states.
+    fn build_microstep(
+        states: &HashMap<String, State>,
+        active: &[String],
+        source: String,
+        transition: Transition,
+    ) -> Microstep {
+        let domain = Self::transition_domain(states, &source, &transition.to_state);
+
+        // Exit every active state below the domain, innermost first
+        let exit_states = active
+            .iter()
+            .rev()
+            .filter(|name| Self::is_descendant(states, name, domain.as_deref()))
+            .filter_map(|name| states.get(name).cloned())
+            .collect();
+        let entry_states = Self::entry_set(states, domain.as_deref(), &transition.to_state)
+            .into_iter()
+            .filter_map(|name| states.get(&name).cloned())
+            .collect();
+        Microstep {
+            source,
+            transition,
+            exit_states,
+            entry_states,
+        }
+    }
+
+    /// Finds the transition for an event, searching the given state and then its ancestors.
+    /// Candidates declared on the same state are tried in declaration order and the first one
+    /// whose guard holds wins. Returns the name of the state that declares the transition along
+    /// with the transition.
+    fn find_transition(
+        states: &HashMap<String, State>,
+        state_name: &str,
+        event: Option<&str>,
+        memory: &Map<String, Value>,
+        payload: &Value,
+    ) -> Result<Option<(String, Transition)>, Error> {
+        for name in Self::ancestors(states, state_name) {
+            let candidates = states.get(&name).and_then(|s| match event {
+                Some(event) => s.transitions.get(event),
+                None => Some(&s.eventless),
+            });
+            let Some(candidates) = candidates else {
+                continue;
+            };
+            for transition in candidates {
+                let enabled = match &transition.guard {
+                    Some(guard) => Self::evaluate_condition(guard, memory, payload)?,
+                    None => true,
+                };
+                if enabled {
+                    return Ok(Some((name, transition.clone())));
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// Returns the given state followed by each of its ancestors, innermost first.
+    fn ancestors(states: &HashMap<String, State>, state_name: &str) -> Vec<String> {
+        let mut path = Vec::new();
+        let mut cursor = states.get(state_name);
+        while let Some(state) = cursor {
+            path.push(state.name.clone());
+            cursor = state.parent.as_ref().and_then(|parent| states.get(parent));
+        }
+        path
+    }
+
+    /// Returns whether `state_name` is nested (at any depth) inside `ancestor`.
+    /// Every state is considered a descendant of the top level (`None`).
+    fn is_descendant(
+        states: &HashMap<String, State>,
+        state_name: &str,
+        ancestor: Option<&str>,
+    ) -> bool {
+        match ancestor {
+            Some(ancestor) => Self::ancestors(states, state_name)
+                .iter()
+                .skip(1)
+                .any(|name| name == ancestor),
+            None => true,
+        }
+    }
+
+    /// Returns the least common compound ancestor that contains a transition from `source` to
+    /// `target`. The source itself is never the domain, so a transition always exits its source
+    /// state, and parallel states are skipped so that all of their regions are exited together.
+    /// `None` means the transition crosses the top level of the state machine.
+    fn transition_domain(
+        states: &HashMap<String, State>,
+        source: &str,
+        target: &str,
+    ) -> Option<String> {
+        let target_ancestors = Self::ancestors(states, target);
+        Self::ancestors(states, source)
+            .into_iter()
+            .skip(1)
+            .filter(|name| states.get(name).is_some_and(|state| !state.parallel))
+            .find(|name| target_ancestors.contains(name))
+    }
+
+    /// Returns the states entered when moving from `domain` into `target`, in document order.
+    ///
+    /// This includes the ancestors of `target` below the domain, the initial descendants of
+    /// `target`, and the initial descendants of every other region of any parallel state that
+    /// is entered on the way.
+    fn entry_set(
+        states: &HashMap<String, State>,
+        domain: Option<&str>,
+        target: &str,
+    ) -> Vec<String> {
+        let mut path: Vec<String> = Self::ancestors(states, target)
+            .into_iter()
+            .take_while(|name| Some(name.as_str()) != domain)
+            .collect();
+        path.reverse();
+
+        let mut entered = Vec::new();
+        for (index, name) in path.iter().enumerate() {
+            let Some(state) = states.get(name) else {
+                continue;
+            };
+            if index + 1 == path.len() {
+                Self::add_initial_descendants(states, name, &mut entered);
             } else {
-                return Err(format!(
-                    "Next state '{}' not found in state machine.",
-                    transition.to_state
-                ));
+                entered.push(name.clone());
+                if state.parallel {
+                    for region in &state.children {
+                        if *region != path[index + 1] {
+                            Self::add_initial_descendants(states, region, &mut entered);
+                        }
+                    }
+                }
             }
-        }; // Lock is released here
+        }
+        Self::sort_by_document_order(states, &mut entered);
+        entered
+    }
 
-        // Now we can call execute_actions with the cloned actions
-        self.execute_actions(&next_state_on_enter_actions, &mut memory, &mut context)
-            .await;
+    /// Adds the given state and the states entered by default below it: the initial child of a
+    /// compound state, or every region of a parallel state.
+    fn add_initial_descendants(
+        states: &HashMap<String, State>,
+        state_name: &str,
+        entered: &mut Vec<String>,
+    ) {
+        let Some(state) = states.get(state_name) else {
+            return;
+        };
+        entered.push(state.name.clone());
+        if state.parallel {
+            for region in &state.children {
+                Self::add_initial_descendants(states, region, entered);
+            }
+        } else if let Some(child) = state.initial.as_ref().or(state.children.first()) {
+            Self::add_initial_descendants(states, child, entered);
+        }
+    }
 
-        Ok(())
+    /// Returns every active state (leaves and their ancestors) in document order.
+    fn active_set(states: &HashMap<String, State>, active_leaves: &[String]) -> Vec<String> {
+        let mut active: Vec<String> = Vec::new();
+        for leaf in active_leaves {
+            for name in Self::ancestors(states, leaf) {
+                if !active.contains(&name) {
+                    active.push(name);
+                }
+            }
+        }
+        Self::sort_by_document_order(states, &mut active);
+        active
+    }
+
+    /// Returns the states of a configuration that have no children, in document order.
+    fn leaves(states: &HashMap<String, State>, configuration: &[String]) -> Vec<String> {
+        let mut leaves: Vec<String> = configuration
+            .iter()
+            .filter(|name| {
+                states
+                    .get(*name)
+                    .is_some_and(|state| state.children.is_empty())
+            })
+            .cloned()
+            .collect();
+        Self::sort_by_document_order(states, &mut leaves);
+        leaves
+    }
+
+    /// Sorts state names in document order, ancestors before descendants.
+    fn sort_by_document_order(states: &HashMap<String, State>, names: &mut [String]) {
+        names.sort_by_key(|name| states.get(name).map_or(usize::MAX, |state| state.order));
     }
 
-    /// Executes a list of actions using the provided async action handler.
+    /// Executes a list of actions using the handlers registered for their types. Built-in
+    /// actions (`set`, `unset`, `increment`, `append`, `merge`, `copy`) update the memory
+    /// directly instead.
+    ///
+    /// The first failure is returned, unless the error policy is `Continue`, in which case
+    /// failures are logged and the remaining actions still run.
     async fn execute_actions<'b>(
         &self,
         actions: &[Action],
         memory: &'b mut Map<String, Value>,
         context: &'b mut C,
-    ) {
+        payload: &'b Value,
+        policy: &ErrorPolicy,
+    ) -> Result<(), Error> {
+        // Actions are turned off while replaying, so there is nothing to render either
+        if self.replaying {
+            return Ok(());
+        }
         for action in actions {
-            (self.action_handler)(action, memory, context).await;
+            let result = match self.render_action(action, memory, payload) {
+                Ok(rendered) => {
+                    let result = if builtins::is_builtin(&rendered.action_type) {
+                        builtins::execute(&rendered, memory, payload).map_err(ActionError::new)
+                    } else {
+                        match self.handlers.get(&rendered.action_type) {
+                            Some(handler) => handler(&rendered, memory, context, payload).await,
+                            None => Err(ActionError::new(
+                                "no handler registered for this action type",
+                            )),
+                        }
+                    };
+                    result.map_err(|error| Error::ActionFailed {
+                        action_type: action.action_type.clone(),
+                        message: error.to_string(),
+                    })
+                }
+                Err(message) => Err(Error::Template {
+                    action_type: action.action_type.clone(),
+                    message,
+                }),
+            };
+            if let Err(error) = result {
+                if *policy != ErrorPolicy::Continue {
+                    return Err(error);
+                }
+                log::warn!("{}", error);
+            }
+        }
+        Ok(())
+    }
+
+    /// Renders the templates in the command and parameters of an action against the memory
+    /// and event payload, then checks the rendered parameters against the schema of the
+    /// action's type.
+    fn render_action(
+        &self,
+        action: &Action,
+        memory: &Map<String, Value>,
+        payload: &Value,
+    ) -> Result<Action, String> {
+        let params = template::render_value(&action.params, memory, payload, self.template_mode)?;
+        if let Some(schema) = self.action_schemas.get(&action.action_type) {
+            schema
+                .validator
+                .validate(&params)
+                .map_err(|error| format!("rendered params are invalid: {}", error))?;
+        }
+        Ok(Action {
+            action_type: action.action_type.clone(),
+            command: Template::parse(&action.command)?.render(
+                memory,
+                payload,
+                self.template_mode,
+            )?,
+            params,
+        })
+    }
+
+    /// Returns, for each microstep, the memory fields its actions must not change: those
+    /// protected in any state active when the step starts, and those protected by the
+    /// microstep's transition.
+    fn collect_protected_fields(
+        &self,
+        microsteps: &[Microstep],
+        active_leaves: &[String],
+        memory: &Map<String, Value>,
+        payload: &Value,
+    ) -> Result<Vec<Vec<ProtectedField>>, Error> {
+        let mut state_fields = Vec::new();
+        {
+            let states_guard = self.states.read().unwrap();
+            for name in Self::active_set(&states_guard, active_leaves) {
+                if let Some(state) = states_guard.get(&name) {
+                    state_fields.extend(Self::protected_fields(
+                        &state.validations,
+                        memory,
+                        payload,
+                    )?);
+                }
+            }
+        } // Lock is released here
+        microsteps
+            .iter()
+            .map(|microstep| {
+                let mut fields = state_fields.clone();
+                fields.extend(Self::protected_fields(
+                    &microstep.transition.validations,
+                    memory,
+                    payload,
+                )?);
+                Ok(fields)
+            })
+            .collect()
+    }
+
+    /// Returns the memory fields protected by the `read_only` and `editable` rules of the given
+    /// validations whose condition holds.
+    fn protected_fields(
+        validations: &[ValidationRule],
+        memory: &Map<String, Value>,
+        payload: &Value,
+    ) -> Result<Vec<ProtectedField>, Error> {
+        let mut protected = Vec::new();
+        for validation in validations {
+            // The event payload cannot be changed by actions
+            if validation.field.is_event() {
+                continue;
+            }
+            if let Some(condition) = &validation.condition {
+                if !Self::evaluate_condition(condition, memory, payload)? {
+                    continue;
+                }
+            }
+            for rule in &validation.rules {
+                let rule = match rule {
+                    FieldRule::ReadOnly { is_read_only: true } => "read_only",
+                    FieldRule::Editable { is_editable: false } => "editable",
+                    _ => continue,
+                };
+                protected.push(ProtectedField {
+                    field: validation.field.clone(),
+                    rule,
+                });
+            }
+        }
+        Ok(protected)
+    }
+
+    /// Returns the memory values a protected field path matches.
+    fn protected_values(field: &FieldPath, memory: &Map<String, Value>) -> Vec<Option<Value>> {
+        field
+            .resolve(memory, &Value::Null)
+            .into_iter()
+            .map(|(_, value)| value.cloned())
+            .collect()
+    }
+
+    /// Checks that the protected fields still hold the values they had before the actions ran.
+    /// In [`ValidationMode::CollectAll`], every changed field is reported.
+    fn check_protected_fields(
+        protected: &[ProtectedField],
+        values: &[Vec<Option<Value>>],
+        memory: &Map<String, Value>,
+        mode: ValidationMode,
+    ) -> Result<(), Error> {
+        let mut violations = Vec::new();
+        for (protected, values) in protected.iter().zip(values) {
+            if Self::protected_values(&protected.field, memory) != *values {
+                let violation = Violation {
+                    field: protected.field.to_string(),
+                    path: protected.field.to_string(),
+                    rule: protected.rule.to_string(),
+                    message: match protected.rule {
+                        "editable" => "is not editable but was changed by an action",
+                        _ => "is read-only but was changed by an action",
+                    }
+                    .to_string(),
+                };
+                match mode {
+                    ValidationMode::CollectAll => violations.push(violation),
+                    ValidationMode::FailFast => return Err(Error::Validation(violation)),
+                }
+            }
+        }
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::Validations(violations))
         }
     }
 
-    /// Evaluates a list of validation rules against the memory.
+    /// Evaluates a list of validation rules against the memory and event payload. In
+    /// [`ValidationMode::CollectAll`], broken rules are added to `violations` instead of failing
+    /// the evaluation.
     fn evaluate_validations(
         validations: &[ValidationRule],
         memory: &Map<String, Value>,
-    ) -> Result<(), String> {
+        payload: &Value,
+        mode: ValidationMode,
+        violations: &mut Vec<Violation>,
+    ) -> Result<(), Error> {
         for validation in validations {
             // Check condition if present
             if let Some(condition) = &validation.condition {
-                if !Self::evaluate_condition(condition, memory)? {
+                if !Self::evaluate_condition(condition, memory, payload)? {
                     // Condition not met, skip validation
                     continue;
                 }
             }
 
-            // Get the value from the memory
-            let field_value = memory.get(&validation.field);
+            // Check every value the field matches in the memory or the payload. A wildcard over a
+            // missing value or a value that is not an array is checked like a missing field,
+            // and the rules requiring a value report why that value has no items
+            let (matches, gaps) = validation.field.resolve_with_gaps(memory, payload);
+            let gaps = gaps.into_iter().map(|(path, value)| {
+                let reason = match value {
+                    Some(value) => {
+                        format!("is of type '{}', not an array", Self::get_type_name(value))
+                    }
+                    None => "is missing in memory".to_string(),
+                };
+                (path, None, Some(reason))
+            });
+            let values = matches.into_iter().map(|(path, value)| (path, value, None));
+            for (path, field_value, reason) in values.chain(gaps) {
+                let violation = |rule: &str, message: String| {
+                    Error::Validation(Violation {
+                        field: validation.field.to_string(),
+                        path: path.clone(),
+                        rule: rule.to_string(),
+                        message: reason.clone().unwrap_or(message),
+                    })
+                };
 
-            for rule in &validation.rules {
-                match rule {
-                    FieldRule::TypeCheck { expected_type } => {
-                        if let Some(value) = field_value {
-                            let actual_type = Self::get_type_name(value);
-                            if actual_type != expected_type {
-                                return Err(format!(
-                                    "Validation failed: Field '{}' expected type '{}', got '{}'",
-                                    validation.field, expected_type, actual_type
-                                ));
-                            }
-                        } else {
-                            return Err(format!(
-                                "Validation failed: Field '{}' is missing in memory",
-                                validation.field
-                            ));
+                for rule in &validation.rules {
+                    match Self::check_rule(rule, field_value, memory, payload, violation) {
+                        Err(Error::Validation(found)) if mode == ValidationMode::CollectAll => {
+                            violations.push(found);
                         }
+                        result => result?,
                     }
-                    FieldRule::Nullable { is_nullable } => {
-                        if !*is_nullable && field_value.is_none() {
-                            return Err(format!(
-                                "Validation failed: Field '{}' cannot be null",
-                                validation.field
-                            ));
-                        }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks a single rule against a value the validated field matches, building the
+    /// violation with `violation` if the rule is not satisfied.
+    fn check_rule(
+        rule: &FieldRule,
+        field_value: Option<&Value>,
+        memory: &Map<String, Value>,
+        payload: &Value,
+        violation: impl Fn(&str, String) -> Error,
+    ) -> Result<(), Error> {
+        match rule {
+            FieldRule::TypeCheck { expected_type } => {
+                if let Some(value) = field_value {
+                    let actual_type = Self::get_type_name(value);
+                    if actual_type != expected_type {
+                        return Err(violation(
+                            "type_check",
+                            format!("expected type '{}', got '{}'", expected_type, actual_type),
+                        ));
                     }
-                    FieldRule::MinValue { value: min_value } => {
-                        if let Some(Value::Number(num)) = field_value {
-                            if num.as_f64().unwrap_or(f64::NAN) < *min_value {
-                                return Err(format!(
-                                    "Validation failed: Field '{}' value '{}' is less than minimum '{}'",
-                                    validation.field, num, min_value
-                                ));
-                            }
-                        } else {
-                            return Err(format!(
-                                "Validation failed: Field '{}' is not a number",
-                                validation.field
-                            ));
-                        }
+                } else {
+                    return Err(violation("type_check", "is missing in memory".into()));
+                }
+            }
+            FieldRule::Nullable { is_nullable } => {
+                if !*is_nullable && field_value.is_none() {
+                    return Err(violation("nullable", "cannot be null".into()));
+                }
+            }
+            FieldRule::MinValue { value } => {
+                let min_value = Self::number_operand(value, memory, payload)
+                    .map_err(|message| violation("min_value", message))?;
+                if let Some(Value::Number(num)) = field_value {
+                    if num.as_f64().unwrap_or(f64::NAN) < min_value {
+                        return Err(violation(
+                            "min_value",
+                            format!("value '{}' is less than minimum '{}'", num, min_value),
+                        ));
                     }
-                    FieldRule::MaxValue { value: max_value } => {
-                        if let Some(Value::Number(num)) = field_value {
-                            if num.as_f64().unwrap_or(f64::NAN) > *max_value {
-                                return Err(format!(
-                                    "Validation failed: Field '{}' value '{}' is greater than maximum '{}'",
-                                    validation.field, num, max_value
-                                ));
-                            }
-                        } else {
-                            return Err(format!(
-                                "Validation failed: Field '{}' is not a number",
-                                validation.field
-                            ));
-                        }
+                } else {
+                    return Err(violation("min_value", "is not a number".into()));
+                }
+            }
+            FieldRule::MaxValue { value } => {
+                let max_value = Self::number_operand(value, memory, payload)
+                    .map_err(|message| violation("max_value", message))?;
+                if let Some(Value::Number(num)) = field_value {
+                    if num.as_f64().unwrap_or(f64::NAN) > max_value {
+                        return Err(violation(
+                            "max_value",
+                            format!("value '{}' is greater than maximum '{}'", num, max_value),
+                        ));
                     }
-                    FieldRule::Editable { is_editable: _ }
-                    | FieldRule::ReadOnly { is_read_only: _ } => {
-                        // Not implemented
+                } else {
+                    return Err(violation("max_value", "is not a number".into()));
+                }
+            }
+            FieldRule::Editable { .. } | FieldRule::ReadOnly { .. } => {
+                // Checked against the memory changes made by the actions
+            }
+            FieldRule::Enum { values } => {
+                if let Some(value) = field_value {
+                    if !values.contains(value) {
+                        return Err(violation(
+                            "enum",
+                            format!("value '{}' is not in enum {:?}", value, values),
+                        ));
                     }
-                    FieldRule::Enum { values } => {
-                        if let Some(value) = field_value {
-                            if !values.contains(value) {
-                                return Err(format!(
-                                    "Validation failed: Field '{}' value '{}' is not in enum {:?}",
-                                    validation.field, value, values
-                                ));
-                            }
-                        } else {
-                            return Err(format!(
-                                "Validation failed: Field '{}' is missing in memory",
-                                validation.field
-                            ));
-                        }
-                    } // Handle more rules as needed
+                } else {
+                    return Err(violation("enum", "is missing in memory".into()));
+                }
+            }
+            FieldRule::MinLength { value } => {
+                let min_length = Self::length_operand(value, memory, payload)
+                    .map_err(|message| violation("min_length", message))?;
+                match field_value.and_then(Self::value_length) {
+                    Some(length) if length < min_length => {
+                        return Err(violation(
+                            "min_length",
+                            format!("length {} is less than minimum {}", length, min_length),
+                        ));
+                    }
+                    Some(_) => {}
+                    None => {
+                        return Err(violation("min_length", "is not a string or array".into()));
+                    }
+                }
+            }
+            FieldRule::MaxLength { value } => {
+                let max_length = Self::length_operand(value, memory, payload)
+                    .map_err(|message| violation("max_length", message))?;
+                match field_value.and_then(Self::value_length) {
+                    Some(length) if length > max_length => {
+                        return Err(violation(
+                            "max_length",
+                            format!("length {} is greater than maximum {}", length, max_length),
+                        ));
+                    }
+                    Some(_) => {}
+                    None => {
+                        return Err(violation("max_length", "is not a string or array".into()));
+                    }
+                }
+            }
+            FieldRule::Pattern { pattern } => {
+                if let Some(Value::String(text)) = field_value {
+                    if !pattern.0.is_match(text) {
+                        return Err(violation(
+                            "pattern",
+                            format!(
+                                "value '{}' does not match pattern '{}'",
+                                text,
+                                pattern.0.as_str()
+                            ),
+                        ));
+                    }
+                } else {
+                    return Err(violation("pattern", "is not a string".into()));
+                }
+            }
+            FieldRule::Format { format } => {
+                if let Some(value @ Value::String(text)) = field_value {
+                    if !format.is_valid(value) {
+                        return Err(violation(
+                            "format",
+                            format!("value '{}' is not a valid {}", text, format.name()),
+                        ));
+                    }
+                } else {
+                    return Err(violation("format", "is not a string".into()));
                 }
             }
+            FieldRule::Contains { value } => {
+                let item = value
+                    .resolve(memory, payload)
+                    .map_err(|message| violation("contains", message))?;
+                let contains = match (field_value, item) {
+                    (Some(Value::Array(items)), _) => items.contains(item),
+                    (Some(Value::String(text)), Value::String(part)) => {
+                        text.contains(part.as_str())
+                    }
+                    _ => {
+                        return Err(violation("contains", "is not a string or array".into()));
+                    }
+                };
+                if !contains {
+                    return Err(violation(
+                        "contains",
+                        format!("does not contain '{}'", item),
+                    ));
+                }
+            }
+            FieldRule::UniqueItems { is_unique } => {
+                if !*is_unique {
+                    return Ok(());
+                }
+                if let Some(Value::Array(items)) = field_value {
+                    // Values are not hashable, so duplicates are found pairwise
+                    let duplicate = items
+                        .iter()
+                        .enumerate()
+                        .find_map(|(i, item)| items[..i].contains(item).then_some(item));
+                    if let Some(duplicate) = duplicate {
+                        return Err(violation(
+                            "unique_items",
+                            format!("contains duplicate item '{}'", duplicate),
+                        ));
+                    }
+                } else {
+                    return Err(violation("unique_items", "is not an array".into()));
+                }
+            }
+            FieldRule::Expr { expr } => {
+                let holds = expr
+                    .0
+                    .evaluate(memory, payload, field_value)
+                    .map_err(|message| violation("expr", message))?;
+                if !holds {
+                    return Err(violation("expr", format!("does not satisfy '{}'", expr.0)));
+                }
+            } // Handle more rules as needed
         }
         Ok(())
     }
 
-    /// Evaluates a condition against the memory.
+    /// Evaluates a condition against the memory and event payload. `all` and `any` stop at the
+    /// first nested condition that decides the result.
     fn evaluate_condition(
         condition: &Condition,
         memory: &Map<String, Value>,
-    ) -> Result<bool, String> {
-        let field_value = memory.get(&condition.field);
-        if let Some(actual_value) = field_value {
-            let result = match condition.operator.as_str() {
-                "==" => actual_value == &condition.value,
-                "!=" => actual_value != &condition.value,
-                ">" => Self::compare_values(
-                    actual_value,
-                    &condition.value,
-                    std::cmp::Ordering::Greater,
-                )?,
-                "<" => {
-                    Self::compare_values(actual_value, &condition.value, std::cmp::Ordering::Less)?
+        payload: &Value,
+    ) -> Result<bool, Error> {
+        match condition {
+            Condition::All { all } => {
+                for condition in all {
+                    if !Self::evaluate_condition(condition, memory, payload)? {
+                        return Ok(false);
+                    }
                 }
-                ">=" => {
-                    let ordering = Self::compare_values_ordering(actual_value, &condition.value)?;
-                    ordering == std::cmp::Ordering::Greater || ordering == std::cmp::Ordering::Equal
+                Ok(true)
+            }
+            Condition::Any { any } => {
+                for condition in any {
+                    if Self::evaluate_condition(condition, memory, payload)? {
+                        return Ok(true);
+                    }
                 }
-                "<=" => {
-                    let ordering = Self::compare_values_ordering(actual_value, &condition.value)?;
-                    ordering == std::cmp::Ordering::Less || ordering == std::cmp::Ordering::Equal
+                Ok(false)
+            }
+            Condition::Not { not } => Ok(!Self::evaluate_condition(not, memory, payload)?),
+            Condition::Compare(comparison) => {
+                Self::evaluate_comparison(comparison, memory, payload)
+            }
+            Condition::Expr(expression) => {
+                expression
+                    .evaluate(memory, payload, None)
+                    .map_err(|message| Error::Condition {
+                        field: expression.to_string(),
+                        message,
+                    })
+            }
+        }
+    }
+
+    /// Evaluates a comparison against the memory and event payload. A comparison on a wildcard
+    /// path holds when it holds for every matched value, and a wildcard over a missing value or
+    /// a value that is not an array is treated like a missing field.
+    fn evaluate_comparison(
+        comparison: &Comparison,
+        memory: &Map<String, Value>,
+        payload: &Value,
+    ) -> Result<bool, Error> {
+        let expected = comparison
+            .value
+            .resolve(memory, payload)
+            .map_err(|message| Error::Condition {
+                field: comparison.field.to_string(),
+                message,
+            })?;
+        let (matches, gaps) = comparison.field.resolve_with_gaps(memory, payload);
+        let values = matches.into_iter().map(|(_, value)| value);
+        for field_value in values.chain(gaps.into_iter().map(|_| None)) {
+            let holds = match (field_value, comparison.operator) {
+                (None, Operator::Missing) => true,
+                (None, Operator::Exists) => false,
+                (None, _) => {
+                    return Err(Error::Condition {
+                        field: comparison.field.to_string(),
+                        message: "Field is missing in memory".into(),
+                    })
                 }
-                _ => return Err(format!("Unsupported operator '{}'", condition.operator)),
+                (Some(actual_value), _) => Self::compare(comparison, actual_value, expected)?,
             };
-            Ok(result)
-        } else {
-            Err(format!(
-                "Condition evaluation failed: Field '{}' is missing in memory",
-                condition.field
-            ))
+            if !holds {
+                return Ok(false);
+            }
         }
+        Ok(true)
     }
 
-    /// Compares two serde_json::Value numbers based on the expected ordering.
-    fn compare_values(
-        actual: &Value,
-        expected: &Value,
-        ordering: std::cmp::Ordering,
-    ) -> Result<bool, String> {
-        let actual_num = actual
-            .as_f64()
-            .ok_or_else(|| format!("Cannot compare non-numeric value '{}' in condition", actual))?;
-        let expected_num = expected.as_f64().ok_or_else(|| {
-            format!(
-                "Cannot compare non-numeric value '{}' in condition",
-                expected
-            )
-        })?;
-        Ok(actual_num.partial_cmp(&expected_num) == Some(ordering))
+    /// Applies the operator of a comparison to a value that is present and the resolved
+    /// expected value.
+    fn compare(comparison: &Comparison, actual: &Value, expected: &Value) -> Result<bool, Error> {
+        let text = |value| Self::as_text(comparison, value);
+        let items = || {
+            expected.as_array().ok_or_else(|| Error::Condition {
+                field: comparison.field.to_string(),
+                message: format!(
+                    "Operator '{}' needs an array value, got '{}'",
+                    comparison.operator, expected
+                ),
+            })
+        };
+        let result = match comparison.operator {
+            Operator::Eq => actual == expected,
+            Operator::Ne => actual != expected,
+            Operator::Gt => Self::order(comparison, actual, expected)?.is_gt(),
+            Operator::Lt => Self::order(comparison, actual, expected)?.is_lt(),
+            Operator::Ge => Self::order(comparison, actual, expected)?.is_ge(),
+            Operator::Le => Self::order(comparison, actual, expected)?.is_le(),
+            Operator::In => items()?.contains(actual),
+            Operator::NotIn => !items()?.contains(actual),
+            Operator::Contains => match actual {
+                Value::Array(items) => items.contains(expected),
+                _ => text(actual)?.contains(text(expected)?),
+            },
+            Operator::Exists => true,
+            Operator::Missing => false,
+            Operator::Matches => {
+                let actual = text(actual)?;
+                match &comparison.pattern {
+                    Some(pattern) => pattern.0.is_match(actual),
+                    // A referenced pattern is only known now
+                    None => regex::Regex::new(text(expected)?)
+                        .map_err(|e| Error::Condition {
+                            field: comparison.field.to_string(),
+                            message: format!("Invalid pattern: {}", e),
+                        })?
+                        .is_match(actual),
+                }
+            }
+            Operator::StartsWith => text(actual)?.starts_with(text(expected)?),
+            Operator::EndsWith => text(actual)?.ends_with(text(expected)?),
+        };
+        Ok(result)
     }
 
-    /// Compares two serde_json::Value numbers and returns the ordering.
-    fn compare_values_ordering(
+    /// Returns a string operand of a comparison, failing if the value is not a string.
+    fn as_text<'v>(comparison: &Comparison, value: &'v Value) -> Result<&'v str, Error> {
+        value.as_str().ok_or_else(|| Error::Condition {
+            field: comparison.field.to_string(),
+            message: format!(
+                "Operator '{}' cannot be applied to non-string value '{}'",
+                comparison.operator, value
+            ),
+        })
+    }
+
+    /// Orders a value against the expected value of a comparison: numbers numerically, RFC 3339
+    /// date-times chronologically and other strings lexicographically.
+    fn order(
+        comparison: &Comparison,
         actual: &Value,
         expected: &Value,
-    ) -> Result<std::cmp::Ordering, String> {
-        let actual_num = actual
+    ) -> Result<std::cmp::Ordering, Error> {
+        let ordering = match (actual, expected) {
+            (Value::Number(actual), Value::Number(expected)) => actual
+                .as_f64()
+                .zip(expected.as_f64())
+                .and_then(|(actual, expected)| actual.partial_cmp(&expected)),
+            (Value::String(actual), Value::String(expected)) => Some(
+                match (
+                    datetime::parse_rfc3339(actual),
+                    datetime::parse_rfc3339(expected),
+                ) {
+                    (Some(actual), Some(expected)) => actual.cmp(&expected),
+                    _ => actual.cmp(expected),
+                },
+            ),
+            _ => None,
+        };
+        ordering.ok_or_else(|| Error::Condition {
+            field: comparison.field.to_string(),
+            message: format!(
+                "Cannot compare '{}' with '{}' in condition",
+                actual, expected
+            ),
+        })
+    }
+
+    /// Resolves the operand of a numeric rule.
+    fn number_operand(
+        operand: &Operand,
+        memory: &Map<String, Value>,
+        payload: &Value,
+    ) -> Result<f64, String> {
+        let value = operand.resolve(memory, payload)?;
+        value
             .as_f64()
-            .ok_or_else(|| format!("Cannot compare non-numeric value '{}' in condition", actual))?;
-        let expected_num = expected.as_f64().ok_or_else(|| {
-            format!(
-                "Cannot compare non-numeric value '{}' in condition",
-                expected
-            )
-        })?;
-        Ok(actual_num
-            .partial_cmp(&expected_num)
-            .unwrap_or(std::cmp::Ordering::Equal))
+            .ok_or_else(|| format!("cannot be compared with non-numeric value '{}'", value))
+    }
+
+    /// Resolves the operand of a length rule.
+    fn length_operand(
+        operand: &Operand,
+        memory: &Map<String, Value>,
+        payload: &Value,
+    ) -> Result<usize, String> {
+        let value = operand.resolve(memory, payload)?;
+        value
+            .as_u64()
+            .and_then(|length| usize::try_from(length).ok())
+            .ok_or_else(|| format!("cannot be compared with invalid length '{}'", value))
+    }
+
+    /// Returns the number of characters of a string or the number of items of an array.
+    fn value_length(value: &Value) -> Option<usize> {
+        match value {
+            Value::String(text) => Some(text.chars().count()),
+            Value::Array(items) => Some(items.len()),
+            _ => None,
+        }
     }
 
     /// Returns a string representing the type of the serde_json::Value.
@@ -735,9 +3090,297 @@ impl<C> StateMachine<'_, C> {
     }
 
     /// Returns the current state of the state machine.
-    pub async fn get_current_state(&self) -> Result<String, String> {
-        let current_state_guard = self.current_state.read().unwrap();
-        Ok(current_state_guard.clone())
+    ///
+    /// For hierarchical configurations this is the innermost active (leaf) state. When a
+    /// parallel state is active, this is the leaf of its first region; use
+    /// [`StateMachine::get_active_configuration`] to see every active state.
+    pub async fn get_current_state(&self) -> Result<String, Error> {
+        let active_states_guard = self.active_states.read().unwrap();
+        active_states_guard
+            .first()
+            .cloned()
+            .ok_or_else(|| Error::UnknownState(String::new()))
+    }
+
+    /// Returns the full path of active states, from the outermost ancestor down to the
+    /// current leaf state.
+    pub async fn get_active_path(&self) -> Result<Vec<String>, Error> {
+        let current_state_name = self.get_current_state().await?;
+        let states_guard = self.states.read().unwrap();
+        let mut path = Self::ancestors(&states_guard, &current_state_name);
+        path.reverse();
+        Ok(path)
+    }
+
+    /// Captures the active states, memory, pending timers and version of the state machine so
+    /// that it can be resumed later with [`StateMachine::restore`].
+    pub async fn snapshot(&self) -> Snapshot {
+        // Holding the memory lock keeps triggers from running while the snapshot is taken
+        let memory = self.memory.read().await;
+        self.capture(&memory)
+    }
+
+    /// Returns the journal, if journaling was enabled with [`StateMachine::with_journal`].
+    pub async fn journal(&self) -> Option<Journal> {
+        let journal = self.journal.as_ref()?;
+        let memory = self.memory.read().await;
+        let journal_guard = journal.read().unwrap();
+        Some(journal_guard.clone().unwrap_or_else(|| Journal {
+            start: self.capture(&memory),
+            entries: Vec::new(),
+        }))
+    }
+
+    /// Builds a snapshot from the given memory and the current active states and timers.
+    fn capture(&self, memory: &Map<String, Value>) -> Snapshot {
+        Snapshot {
+            config_hash: self.config_fingerprint,
+            current_state: self.active_states.read().unwrap().clone(),
+            memory: memory.clone(),
+            pending_timers: self.timers.read().unwrap().clone(),
+            version: self.version.load(Ordering::SeqCst),
+        }
+    }
+
+    /// Returns the number of steps completed so far. Every event, timed transition and round
+    /// of eventless transitions that changes the active states counts as one step.
+    pub fn version(&self) -> u64 {
+        self.version.load(Ordering::SeqCst)
+    }
+
+    /// Subscribes to the steps taken by the machine. Every event, timed transition and round of
+    /// eventless transitions is published as a [`TransitionEvent`], including events that were
+    /// rejected (no transition, invalid payload, failed validation or action).
+    ///
+    /// The channel buffers 256 events; a subscriber that falls further behind receives
+    /// [`broadcast::error::RecvError::Lagged`] and skips the oldest events.
+    pub fn subscribe(&self) -> broadcast::Receiver<TransitionEvent> {
+        self.events.subscribe()
+    }
+
+    /// Returns a receiver that always holds the current active leaf states, updated after
+    /// every step.
+    pub fn watch_state(&self) -> watch::Receiver<Vec<String>> {
+        self.current_state.subscribe()
+    }
+
+    /// Returns whether the machine is done, i.e. every active leaf state is a final state.
+    pub fn is_done(&self) -> bool {
+        let active_states_guard = self.active_states.read().unwrap();
+        let states_guard = self.states.read().unwrap();
+        Self::all_final(&states_guard, &active_states_guard)
+    }
+
+    /// Waits until the machine is done and returns the final state along with a snapshot of the
+    /// memory taken once the transition into it has completed. Resolves immediately if the
+    /// machine is already done.
+    ///
+    /// With parallel regions, the machine is done once every region has reached a final state
+    /// and the returned state is the final state of the first region.
+    pub async fn wait_for_completion(&self) -> (String, Map<String, Value>) {
+        loop {
+            let completed = self.completed.notified();
+            tokio::pin!(completed);
+            completed.as_mut().enable();
+            if self.is_done() {
+                // Waiting for the memory lock also waits for the running trigger to finish, which
+                // may still roll the transition back
+                let memory = self.memory.read().await;
+                if self.is_done() {
+                    let final_state = self.active_states.read().unwrap()[0].clone();
+                    return (final_state, memory.clone());
+                }
+                continue;
+            }
+            completed.await;
+        }
+    }
+
+    /// Returns whether every given leaf state is a final state.
+    fn all_final(states: &HashMap<String, State>, leaves: &[String]) -> bool {
+        !leaves.is_empty()
+            && leaves
+                .iter()
+                .all(|leaf| states.get(leaf).is_some_and(|state| state.is_final))
+    }
+
+    /// Returns every active state in document order: each active leaf together with all of its
+    /// ancestors, including every active region of a parallel state.
+    pub async fn get_active_configuration(&self) -> Result<Vec<String>, Error> {
+        let active_states_guard = self.active_states.read().unwrap();
+        let states_guard = self.states.read().unwrap();
+        Ok(Self::active_set(&states_guard, &active_states_guard))
+    }
+}
+
+/// An action handler that does nothing, used to replay journaled steps with actions turned off.
+fn skip_action<'a>(
+    _action: &'a Action,
+    _memory: &'a mut Map<String, Value>,
+    _context: &'a mut (),
+    _payload: &'a Value,
+) -> std::pin::Pin<Box<dyn Future<Output = Result<(), ActionError>> + Send + 'a>> {
+    Box::pin(async { Ok(()) })
+}
+
+impl StateMachine<'_, ()> {
+    /// Rebuilds an instance from its journal by restoring the journal's starting snapshot and
+    /// re-taking every recorded step with actions turned off. Instead of running actions, the
+    /// memory changes recorded for each step are applied.
+    ///
+    /// Every step that cannot be taken, or that does not end in the recorded active states, is
+    /// reported as a [`Divergence`]; the replay then continues from the recorded states so that
+    /// later steps are checked on their own.
+    pub async fn replay(config_content: &str, journal: &Journal) -> Result<ReplayReport, Error> {
+        let start_ms = journal
+            .entries
+            .first()
+            .map_or(0, |entry| entry.timestamp_ms);
+        let clock = Arc::new(ManualClock::new(start_ms));
+        let handlers = HandlerRegistry::new().with_fallback(skip_action);
+        let mut state_machine = Self::restore(config_content, journal.start.clone(), handlers, ())?
+            .with_clock(clock.clone());
+        state_machine.replaying = true;
+        let mut memory = state_machine.memory.write().await;
+        let mut context = state_machine.context.write().await;
+
+        let mut payload = Value::Null;
+        let mut divergences = Vec::new();
+        for entry in &journal.entries {
+            clock.set(entry.timestamp_ms);
+            // Eventless rounds see the payload of the event that led to them
+            match &entry.trigger {
+                JournalTrigger::Event { payload: p, .. } => payload = p.clone(),
+                JournalTrigger::Timer { .. } => payload = Value::Null,
+                JournalTrigger::Eventless => {}
+            }
+            let result = state_machine
+                .replay_step(
+                    &entry.trigger,
+                    entry.error.is_some(),
+                    &mut memory,
+                    &mut context,
+                    &payload,
+                )
+                .await;
+            entry.memory_diff.apply(&mut memory);
+
+            let replayed = state_machine.active_states.read().unwrap().clone();
+            if result.is_err() || replayed != entry.to {
+                divergences.push(Divergence {
+                    version: entry.version,
+                    recorded: entry.to.clone(),
+                    replayed,
+                    error: result.err().map(|error| error.to_string()),
+                });
+                // Resynchronize with the journal
+                let states_guard = state_machine.states.read().unwrap();
+                let active = Self::active_set(&states_guard, &entry.to);
+                state_machine
+                    .timers
+                    .write()
+                    .unwrap()
+                    .retain(|timer| active.contains(&timer.state));
+                *state_machine.active_states.write().unwrap() = entry.to.clone();
+            }
+            state_machine.version.store(entry.version, Ordering::SeqCst);
+        }
+
+        Ok(ReplayReport {
+            snapshot: state_machine.capture(&memory),
+            divergences,
+        })
+    }
+
+    /// Re-takes a single journaled step. Actions do not run during a replay, so the failure of
+    /// a step that was `redirected` to an `on_error` state is re-enacted on its first microstep
+    /// that redirects on errors: the microsteps before it are taken and it is redirected.
+    async fn replay_step(
+        &self,
+        trigger: &JournalTrigger,
+        redirected: bool,
+        memory: &mut Map<String, Value>,
+        context: &mut (),
+        payload: &Value,
+    ) -> Result<(), Error> {
+        let active_leaves = self.active_states.read().unwrap().clone();
+        let microsteps = {
+            let states_guard = self.states.read().unwrap();
+            match trigger {
+                JournalTrigger::Event { name, .. } => Self::select_transitions(
+                    &states_guard,
+                    &active_leaves,
+                    Some(name),
+                    memory,
+                    payload,
+                )?,
+                JournalTrigger::Eventless => {
+                    Self::select_transitions(&states_guard, &active_leaves, None, memory, payload)?
+                }
+                JournalTrigger::Timer { state, index } => {
+                    self.timers
+                        .write()
+                        .unwrap()
+                        .retain(|timer| timer.state != *state || timer.index != *index);
+                    vec![Self::timer_microstep(
+                        &states_guard,
+                        &active_leaves,
+                        state,
+                        *index,
+                    )?]
+                }
+            }
+        }; // Lock is released here
+        if microsteps.is_empty() {
+            return Err(Error::NoTransition {
+                event: match trigger {
+                    JournalTrigger::Event { name, .. } => name.clone(),
+                    // A timed transition always yields a microstep
+                    _ => "<eventless>".to_string(),
+                },
+                state: active_leaves.join(", "),
+            });
+        }
+        let failed = microsteps
+            .iter()
+            .enumerate()
+            .find_map(
+                |(index, microstep)| match microstep.transition.error_policy {
+                    ErrorPolicy::Redirect => {
+                        microstep.transition.on_error.as_ref().map(|t| (index, t))
+                    }
+                    _ => None,
+                },
+            );
+        if let (true, Some((failed, on_error))) = (redirected, failed) {
+            let mut active = {
+                let states_guard = self.states.read().unwrap();
+                Self::active_set(&states_guard, &active_leaves)
+            };
+            for microstep in &microsteps[..failed] {
+                self.run_microstep(microstep, &mut active, memory, context, payload)
+                    .await?;
+            }
+            self.redirect(
+                &microsteps[failed],
+                on_error,
+                &mut active,
+                memory,
+                context,
+                payload,
+            )
+            .await;
+            return Ok(());
+        }
+        self.execute_microsteps(
+            &microsteps,
+            active_leaves,
+            memory,
+            context,
+            payload,
+            trigger.clone(),
+        )
+        .await
     }
 }
 
@@ -745,21 +3388,54 @@ impl<C> StateMachine<'_, C> {
 impl<C> Display for StateMachine<'_, C> {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         let states = self.states.read().unwrap();
-        let current_state = self.current_state.read().unwrap();
+        let active_states = self.active_states.read().unwrap();
 
         writeln!(f, "State Machine Diagram:")?;
         writeln!(f, "======================")?;
 
+        let active_set = Self::active_set(&states, &active_states);
         for (state_name, state) in &*states {
-            let marker = if *state_name == *current_state {
-                "->" // Indicate the current state
+            let marker = if active_states.contains(state_name) {
+                "->" // Indicate an active leaf state
+            } else if active_set.contains(state_name) {
+                "=>" // Indicate an active ancestor of the current state
             } else {
                 "  "
             };
-            writeln!(f, "{} State: {}", marker, state.name)?;
+            let kind = if state.is_final { " [final]" } else { "" };
+            match &state.parent {
+                Some(parent) => writeln!(
+                    f,
+                    "{} State: {}{} (in {})",
+                    marker, state.name, kind, parent
+                )?,
+                None => writeln!(f, "{} State: {}{}", marker, state.name, kind)?,
+            }
 
-            for (event, transition) in &state.transitions {
-                writeln!(f, "      -[{}]-> {}", event, transition.to_state)?;
+            for after in &state.after {
+                writeln!(
+                    f,
+                    "      -[after {}ms]-> {}",
+                    after.delay_ms, after.transition.to_state
+                )?;
+            }
+            for transition in &state.eventless {
+                match &transition.guard {
+                    Some(guard) => writeln!(f, "      -[if {}]-> {}", guard, transition.to_state)?,
+                    None => writeln!(f, "      -[always]-> {}", transition.to_state)?,
+                }
+            }
+            for (event, transitions) in &state.transitions {
+                for transition in transitions {
+                    match &transition.guard {
+                        Some(guard) => writeln!(
+                            f,
+                            "      -[{} if {}]-> {}",
+                            event, guard, transition.to_state
+                        )?,
+                        None => writeln!(f, "      -[{}]-> {}", event, transition.to_state)?,
+                    }
+                }
             }
         }
 
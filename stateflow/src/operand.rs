@@ -0,0 +1,86 @@
+//! Values of conditions and rules, which are either literals or references to other fields.
+
+use crate::path::FieldPath;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::fmt::{self, Display, Formatter};
+
+/// The key of an object referring to another field instead of holding a literal value.
+const REF_FIELD: &str = "$ref_field";
+
+/// A literal value, or a reference to a field of the memory or the event payload written as
+/// `{"$ref_field": "path"}`, resolved when the condition or rule is evaluated.
+#[derive(Debug, Clone)]
+pub(crate) enum Operand {
+    /// A value written in the configuration.
+    Literal(Value),
+    /// The value of another field.
+    Field(FieldPath),
+}
+
+impl Operand {
+    /// Returns the literal value, if the operand is not a reference.
+    pub(crate) fn literal(&self) -> Option<&Value> {
+        match self {
+            Operand::Literal(value) => Some(value),
+            Operand::Field(_) => None,
+        }
+    }
+
+    /// Returns the value of the operand, failing if a referenced field is missing.
+    pub(crate) fn resolve<'v>(
+        &'v self,
+        memory: &'v Map<String, Value>,
+        payload: &'v Value,
+    ) -> Result<&'v Value, String> {
+        match self {
+            Operand::Literal(value) => Ok(value),
+            Operand::Field(path) => path
+                .get(memory, payload)?
+                .ok_or_else(|| format!("referenced field '{}' is missing", path)),
+        }
+    }
+}
+
+impl Display for Operand {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Operand::Literal(value) => write!(f, "{}", value),
+            Operand::Field(path) => write!(f, "{}", path),
+        }
+    }
+}
+
+impl Serialize for Operand {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Operand::Literal(value) => value.serialize(serializer),
+            Operand::Field(path) => {
+                let mut reference = Map::new();
+                reference.insert(REF_FIELD.to_string(), Value::String(path.to_string()));
+                reference.serialize(serializer)
+            }
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Operand {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = Value::deserialize(deserializer)?;
+        let Some(reference) = value.as_object().and_then(|fields| fields.get(REF_FIELD)) else {
+            return Ok(Operand::Literal(value));
+        };
+        let path = match (reference, value.as_object().map(Map::len)) {
+            (Value::String(path), Some(1)) => path,
+            _ => {
+                return Err(serde::de::Error::custom(format!(
+                    "a field reference must be {{\"{}\": \"<field>\"}}, got {}",
+                    REF_FIELD, value
+                )))
+            }
+        };
+        let path = FieldPath::parse(path).map_err(serde::de::Error::custom)?;
+        path.check_single().map_err(serde::de::Error::custom)?;
+        Ok(Operand::Field(path))
+    }
+}
@@ -0,0 +1,340 @@
+//! Field paths locating values in the memory or the event payload.
+//!
+//! A path is either dotted (`order.customer.address.country`, `items[0].sku`, `items[*].qty`)
+//! or a JSON Pointer (`/order/items/0/sku`). A path starting with `event` points into the event
+//! payload instead of the memory.
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::fmt::{self, Display, Formatter};
+
+/// Values matched by a field path, along with their concrete paths.
+pub(crate) type Matches<'v> = Vec<(String, Option<&'v Value>)>;
+
+/// A step of a field path.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Step {
+    /// An object key, or an index when the value is an array.
+    Key(String),
+    /// Every item of an array.
+    Wildcard,
+}
+
+/// A parsed field path.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct FieldPath {
+    text: String,
+    pointer: bool,
+    event: bool,
+    steps: Vec<Step>,
+}
+
+impl FieldPath {
+    /// Parses a dotted path or a JSON Pointer.
+    pub(crate) fn parse(text: &str) -> Result<Self, String> {
+        let mut path = Self::parse_in(text, false)?;
+        if path.steps.len() > 1 && path.steps[0] == Step::Key("event".to_string()) {
+            path.steps.remove(0);
+            path.event = true;
+        }
+        Ok(path)
+    }
+
+    /// Parses a dotted path or a JSON Pointer into the event payload if `event` is set, or into
+    /// the memory otherwise. Unlike [`FieldPath::parse`], the path has no `event` prefix.
+    pub(crate) fn parse_in(text: &str, event: bool) -> Result<Self, String> {
+        let pointer = text.starts_with('/');
+        let steps = if pointer {
+            Self::parse_pointer(text)?
+        } else {
+            Self::parse_dotted(text)?
+        };
+        if !event && steps[0] == Step::Wildcard {
+            return Err(format!("path '{}' must start with a memory field", text));
+        }
+        Ok(FieldPath {
+            text: text.to_string(),
+            pointer,
+            event,
+            steps,
+        })
+    }
+
+    /// Parses a JSON Pointer such as `/order/items/0/sku`.
+    fn parse_pointer(text: &str) -> Result<Vec<Step>, String> {
+        text[1..]
+            .split('/')
+            .map(|token| {
+                if token.is_empty() {
+                    return Err(format!("path '{}' has an empty segment", text));
+                }
+                Ok(Step::Key(token.replace("~1", "/").replace("~0", "~")))
+            })
+            .collect()
+    }
+
+    /// Parses a dotted path such as `items[*].qty`, where brackets hold an index or `*`.
+    fn parse_dotted(text: &str) -> Result<Vec<Step>, String> {
+        let malformed = || format!("path '{}' is malformed", text);
+        let mut steps = Vec::new();
+        for part in text.split('.') {
+            let (name, mut brackets) = part.split_at(part.find('[').unwrap_or(part.len()));
+            if name.trim().is_empty() {
+                return Err(format!("path '{}' has an empty segment", text));
+            }
+            steps.push(Step::Key(name.to_string()));
+            while !brackets.is_empty() {
+                let end = brackets.find(']').ok_or_else(malformed)?;
+                let index = brackets.strip_prefix('[').ok_or_else(malformed)?;
+                steps.push(match &index[..end - 1] {
+                    "*" => Step::Wildcard,
+                    index if index.parse::<usize>().is_ok() => Step::Key(index.to_string()),
+                    _ => return Err(malformed()),
+                });
+                brackets = &brackets[end + 1..];
+            }
+        }
+        Ok(steps)
+    }
+
+    /// Returns whether the path points into the event payload.
+    pub(crate) fn is_event(&self) -> bool {
+        self.event
+    }
+
+    /// Returns every value the path matches, along with the concrete path of each match in
+    /// which wildcards are replaced by indexes. A path without wildcards has exactly one match,
+    /// which is `None` if the value is missing. A wildcard over a missing value or a value that
+    /// is not an array matches nothing; see [`FieldPath::resolve_with_gaps`].
+    pub(crate) fn resolve<'v>(
+        &self,
+        memory: &'v Map<String, Value>,
+        payload: &'v Value,
+    ) -> Matches<'v> {
+        self.resolve_with_gaps(memory, payload).0
+    }
+
+    /// Like [`FieldPath::resolve`], but also returns the values a wildcard was applied to that
+    /// are missing or not arrays, along with their concrete paths.
+    pub(crate) fn resolve_with_gaps<'v>(
+        &self,
+        memory: &'v Map<String, Value>,
+        payload: &'v Value,
+    ) -> (Matches<'v>, Matches<'v>) {
+        let (mut matches, steps) = if self.event {
+            let root = if self.pointer { "/event" } else { "event" };
+            (vec![(root.to_string(), Some(payload))], &self.steps[..])
+        } else {
+            let Step::Key(key) = &self.steps[0] else {
+                unreachable!("memory paths start with a key")
+            };
+            let root = if self.pointer {
+                format!("/{}", Self::escape(key))
+            } else {
+                key.clone()
+            };
+            (vec![(root, memory.get(key))], &self.steps[1..])
+        };
+        let mut gaps = Vec::new();
+        for step in steps {
+            matches = matches
+                .into_iter()
+                .flat_map(|(path, value)| match step {
+                    Step::Key(key) => {
+                        vec![(
+                            self.join(&path, key),
+                            value.and_then(|v| Self::child(v, key)),
+                        )]
+                    }
+                    Step::Wildcard => {
+                        if let Some(Value::Array(items)) = value {
+                            items
+                                .iter()
+                                .enumerate()
+                                .map(|(index, item)| (self.join_index(&path, index), Some(item)))
+                                .collect()
+                        } else {
+                            gaps.push((path, value));
+                            Vec::new()
+                        }
+                    }
+                })
+                .collect();
+        }
+        (matches, gaps)
+    }
+
+    /// Returns the value the path points to, failing if the path has a wildcard.
+    pub(crate) fn get<'v>(
+        &self,
+        memory: &'v Map<String, Value>,
+        payload: &'v Value,
+    ) -> Result<Option<&'v Value>, String> {
+        self.check_single()?;
+        Ok(self
+            .resolve(memory, payload)
+            .pop()
+            .and_then(|(_, value)| value))
+    }
+
+    /// Returns the memory value the path points to, inserting `default` if it is missing.
+    /// Missing objects along the way are created.
+    pub(crate) fn entry<'m>(
+        &self,
+        memory: &'m mut Map<String, Value>,
+        default: Value,
+    ) -> Result<&'m mut Value, String> {
+        self.check_writable()?;
+        let (first, rest) = self.keys();
+        let mut value = memory
+            .entry(first.to_string())
+            .or_insert_with(|| Self::placeholder(&rest, &default));
+        for (i, &key) in rest.iter().enumerate() {
+            value = match value {
+                Value::Object(fields) => fields
+                    .entry(key.to_string())
+                    .or_insert_with(|| Self::placeholder(&rest[i + 1..], &default)),
+                Value::Array(items) => key
+                    .parse::<usize>()
+                    .ok()
+                    .and_then(|index| items.get_mut(index))
+                    .ok_or_else(|| format!("'{}' has no item {}", self.text, key))?,
+                _ => return Err(format!("'{}' does not lead to an object", self.text)),
+            };
+        }
+        Ok(value)
+    }
+
+    /// Removes the memory value the path points to, if any.
+    pub(crate) fn remove(&self, memory: &mut Map<String, Value>) -> Result<(), String> {
+        self.check_writable()?;
+        let (first, rest) = self.keys();
+        let Some((last, parents)) = rest.split_last() else {
+            memory.remove(first);
+            return Ok(());
+        };
+        let mut value = memory.get_mut(first);
+        for key in parents {
+            value = value.and_then(|value| Self::child_mut(value, key));
+        }
+        match value {
+            Some(Value::Object(fields)) => {
+                fields.remove(*last);
+            }
+            Some(Value::Array(items)) => {
+                if let Some(index) = last.parse::<usize>().ok().filter(|i| *i < items.len()) {
+                    items.remove(index);
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Returns whether the path has a wildcard.
+    pub(crate) fn has_wildcard(&self) -> bool {
+        self.steps.contains(&Step::Wildcard)
+    }
+
+    /// Fails if the path has a wildcard.
+    pub(crate) fn check_single(&self) -> Result<(), String> {
+        if self.has_wildcard() {
+            return Err(format!("path '{}' cannot use a wildcard here", self.text));
+        }
+        Ok(())
+    }
+
+    /// Fails if the path has a wildcard or points into the event payload.
+    pub(crate) fn check_writable(&self) -> Result<(), String> {
+        self.check_single()?;
+        if self.event {
+            return Err(format!(
+                "the event payload cannot be changed ('{}')",
+                self.text
+            ));
+        }
+        Ok(())
+    }
+
+    /// Returns the keys of a memory path without wildcards, split into the first and the rest.
+    fn keys(&self) -> (&str, Vec<&str>) {
+        let mut keys = self.steps.iter().filter_map(|step| match step {
+            Step::Key(key) => Some(key.as_str()),
+            Step::Wildcard => None,
+        });
+        let first = keys.next().unwrap_or_default();
+        (first, keys.collect())
+    }
+
+    /// Returns the value to insert for a missing key: the default at the end of the path, or an
+    /// object leading to the remaining keys.
+    fn placeholder(remaining: &[&str], default: &Value) -> Value {
+        if remaining.is_empty() {
+            default.clone()
+        } else {
+            Value::Object(Map::new())
+        }
+    }
+
+    /// Returns the child of an object by key or of an array by index.
+    fn child<'v>(value: &'v Value, key: &str) -> Option<&'v Value> {
+        match value {
+            Value::Object(fields) => fields.get(key),
+            Value::Array(items) => key.parse::<usize>().ok().and_then(|i| items.get(i)),
+            _ => None,
+        }
+    }
+
+    /// Returns the mutable child of an object by key or of an array by index.
+    fn child_mut<'v>(value: &'v mut Value, key: &str) -> Option<&'v mut Value> {
+        match value {
+            Value::Object(fields) => fields.get_mut(key),
+            Value::Array(items) => key.parse::<usize>().ok().and_then(|i| items.get_mut(i)),
+            _ => None,
+        }
+    }
+
+    /// Appends a key to a concrete path.
+    fn join(&self, path: &str, key: &str) -> String {
+        if self.pointer {
+            format!("{}/{}", path, Self::escape(key))
+        } else if key.parse::<usize>().is_ok() {
+            format!("{}[{}]", path, key)
+        } else {
+            format!("{}.{}", path, key)
+        }
+    }
+
+    /// Appends an array index to a concrete path.
+    fn join_index(&self, path: &str, index: usize) -> String {
+        if self.pointer {
+            format!("{}/{}", path, index)
+        } else {
+            format!("{}[{}]", path, index)
+        }
+    }
+
+    /// Escapes a key as a JSON Pointer token.
+    fn escape(key: &str) -> String {
+        key.replace('~', "~0").replace('/', "~1")
+    }
+}
+
+impl Display for FieldPath {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.text)
+    }
+}
+
+impl Serialize for FieldPath {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.text.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for FieldPath {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        FieldPath::parse(&text).map_err(serde::de::Error::custom)
+    }
+}
@@ -0,0 +1,76 @@
+//! Serializable snapshots of a running state machine.
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+/// A timed transition scheduled when its state was entered, identified by the state name and
+/// the index of the transition in the state's `after` list.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PendingTimer {
+    /// The state that declares the timed transition.
+    pub state: String,
+    /// The position of the transition in the state's `after` list.
+    pub index: usize,
+    /// When the transition is due, in milliseconds since the Unix epoch.
+    pub due_at_ms: u64,
+}
+
+/// Everything needed to resume a state machine instance: see [`crate::StateMachine::snapshot`]
+/// and [`crate::StateMachine::restore`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Snapshot {
+    /// Fingerprint of the configuration the snapshot was taken from. It ignores formatting and
+    /// the order of object keys, and does not change between Rust releases.
+    pub config_hash: u64,
+    /// The active leaf states, in document order.
+    pub current_state: Vec<String>,
+    /// The memory of the state machine.
+    pub memory: Map<String, Value>,
+    /// The timed transitions that had not fired yet.
+    pub pending_timers: Vec<PendingTimer>,
+    /// The number of steps the state machine had completed.
+    pub version: u64,
+}
+
+/// Computes the fingerprint of a parsed configuration recorded in snapshots: the 64-bit FNV-1a
+/// hash of its canonical JSON text, in which object keys are sorted and there is no whitespace.
+/// Unlike the standard library's hasher, the result does not depend on the Rust release, and
+/// reformatting the configuration does not change it.
+pub(crate) fn config_fingerprint(config: &Value) -> u64 {
+    let mut text = String::new();
+    write_canonical(config, &mut text);
+    text.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// Writes a JSON value with its object keys sorted, whatever order the map keeps them in.
+fn write_canonical(value: &Value, text: &mut String) {
+    match value {
+        Value::Object(fields) => {
+            let mut keys: Vec<&String> = fields.keys().collect();
+            keys.sort();
+            text.push('{');
+            for (i, key) in keys.into_iter().enumerate() {
+                if i > 0 {
+                    text.push(',');
+                }
+                text.push_str(&Value::String(key.clone()).to_string());
+                text.push(':');
+                write_canonical(&fields[key], text);
+            }
+            text.push('}');
+        }
+        Value::Array(items) => {
+            text.push('[');
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    text.push(',');
+                }
+                write_canonical(item, text);
+            }
+            text.push(']');
+        }
+        scalar => text.push_str(&scalar.to_string()),
+    }
+}
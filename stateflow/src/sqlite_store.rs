@@ -0,0 +1,114 @@
+//! A [`StateStore`] backed by an embedded SQLite database.
+
+use crate::{Error, Snapshot, StateStore};
+use rusqlite::{params, Connection, OptionalExtension};
+use std::path::Path;
+use std::sync::Mutex;
+
+/// A store keeping snapshots in a `stateflow_instances` table of a SQLite database.
+///
+/// Compare-and-swap is a single conditional `INSERT`/`UPDATE`, so several processes can share
+/// the same database file.
+#[derive(Debug)]
+pub struct SqliteStore {
+    connection: Mutex<Connection>,
+}
+
+fn sqlite_error(error: rusqlite::Error) -> Error {
+    Error::Store(error.to_string())
+}
+
+impl SqliteStore {
+    /// Opens (or creates) the database at the given path.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, Error> {
+        Self::from_connection(Connection::open(path).map_err(sqlite_error)?)
+    }
+
+    /// Opens a private in-memory database.
+    pub fn open_in_memory() -> Result<Self, Error> {
+        Self::from_connection(Connection::open_in_memory().map_err(sqlite_error)?)
+    }
+
+    /// Uses an existing connection, creating the table if needed.
+    pub fn from_connection(connection: Connection) -> Result<Self, Error> {
+        connection
+            .execute_batch(
+                "CREATE TABLE IF NOT EXISTS stateflow_instances (
+                    instance_id TEXT PRIMARY KEY,
+                    version INTEGER NOT NULL,
+                    snapshot TEXT NOT NULL
+                )",
+            )
+            .map_err(sqlite_error)?;
+        Ok(SqliteStore {
+            connection: Mutex::new(connection),
+        })
+    }
+}
+
+impl StateStore for SqliteStore {
+    fn load(&self, instance_id: &str) -> Result<Option<Snapshot>, Error> {
+        let connection = self.connection.lock().unwrap();
+        let content: Option<String> = connection
+            .query_row(
+                "SELECT snapshot FROM stateflow_instances WHERE instance_id = ?1",
+                params![instance_id],
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(sqlite_error)?;
+        content
+            .map(|content| serde_json::from_str(&content))
+            .transpose()
+            .map_err(|e| Error::Store(e.to_string()))
+    }
+
+    fn save(&self, instance_id: &str, snapshot: &Snapshot) -> Result<(), Error> {
+        let content = serde_json::to_string(snapshot).map_err(|e| Error::Store(e.to_string()))?;
+        let connection = self.connection.lock().unwrap();
+        connection
+            .execute(
+                "INSERT INTO stateflow_instances (instance_id, version, snapshot)
+                 VALUES (?1, ?2, ?3)
+                 ON CONFLICT (instance_id) DO UPDATE
+                 SET version = excluded.version, snapshot = excluded.snapshot",
+                params![instance_id, snapshot.version as i64, content],
+            )
+            .map_err(sqlite_error)?;
+        Ok(())
+    }
+
+    fn compare_and_swap(
+        &self,
+        instance_id: &str,
+        expected_version: u64,
+        snapshot: &Snapshot,
+    ) -> Result<bool, Error> {
+        let content = serde_json::to_string(snapshot).map_err(|e| Error::Store(e.to_string()))?;
+        let connection = self.connection.lock().unwrap();
+        // A missing instance counts as version 0, so the first step inserts the row
+        let changed = if expected_version == 0 {
+            connection.execute(
+                "INSERT INTO stateflow_instances (instance_id, version, snapshot)
+                 VALUES (?1, ?2, ?3)
+                 ON CONFLICT (instance_id) DO UPDATE
+                 SET version = excluded.version, snapshot = excluded.snapshot
+                 WHERE stateflow_instances.version = 0",
+                params![instance_id, snapshot.version as i64, content],
+            )
+        } else {
+            connection.execute(
+                "UPDATE stateflow_instances SET version = ?2, snapshot = ?3
+                 WHERE instance_id = ?1 AND version = ?4",
+                params![
+                    instance_id,
+                    snapshot.version as i64,
+                    content,
+                    expected_version as i64
+                ],
+            )
+        }
+        .map_err(sqlite_error)?;
+        Ok(changed == 1)
+    }
+}
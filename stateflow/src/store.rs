@@ -0,0 +1,73 @@
+//! Persistence backends that record a snapshot of a state machine after every step.
+
+use crate::{Error, Snapshot};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// A durable store of state machine snapshots, keyed by instance id.
+///
+/// Versions implement optimistic concurrency: a state machine attached with
+/// [`crate::StateMachine::with_store`] saves each step with
+/// [`StateStore::compare_and_swap`], expecting the version it started the step from. A missing
+/// instance counts as version 0, the version of a freshly created state machine.
+///
+/// The state machine calls the store from tokio's blocking thread pool, so implementations
+/// may do blocking I/O.
+pub trait StateStore: Send + Sync {
+    /// Loads the latest snapshot of an instance, if any.
+    fn load(&self, instance_id: &str) -> Result<Option<Snapshot>, Error>;
+
+    /// Saves a snapshot unconditionally, replacing any existing one.
+    fn save(&self, instance_id: &str, snapshot: &Snapshot) -> Result<(), Error>;
+
+    /// Saves a snapshot only if the stored version of the instance is `expected_version`.
+    /// Returns `false`, without saving, if another writer got there first.
+    fn compare_and_swap(
+        &self,
+        instance_id: &str,
+        expected_version: u64,
+        snapshot: &Snapshot,
+    ) -> Result<bool, Error>;
+}
+
+/// A store that keeps snapshots in memory, for tests and single-process use.
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    snapshots: Mutex<HashMap<String, Snapshot>>,
+}
+
+impl MemoryStore {
+    /// Creates an empty store.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl StateStore for MemoryStore {
+    fn load(&self, instance_id: &str) -> Result<Option<Snapshot>, Error> {
+        Ok(self.snapshots.lock().unwrap().get(instance_id).cloned())
+    }
+
+    fn save(&self, instance_id: &str, snapshot: &Snapshot) -> Result<(), Error> {
+        self.snapshots
+            .lock()
+            .unwrap()
+            .insert(instance_id.to_string(), snapshot.clone());
+        Ok(())
+    }
+
+    fn compare_and_swap(
+        &self,
+        instance_id: &str,
+        expected_version: u64,
+        snapshot: &Snapshot,
+    ) -> Result<bool, Error> {
+        let mut snapshots = self.snapshots.lock().unwrap();
+        let stored_version = snapshots.get(instance_id).map_or(0, |s| s.version);
+        if stored_version != expected_version {
+            return Ok(false);
+        }
+        snapshots.insert(instance_id.to_string(), snapshot.clone());
+        Ok(true)
+    }
+}
@@ -0,0 +1,192 @@
+//! A small template engine rendering `{{memory.field}}` and `{{event.field}}` placeholders in
+//! action commands and parameters.
+//!
+//! The source is followed by a dotted [`FieldPath`] (`{{memory.items[0].sku}}`) or a JSON
+//! Pointer (`{{memory/items/0/sku}}`).
+
+use crate::path::FieldPath;
+use serde_json::{Map, Value};
+
+/// How placeholders referring to missing fields are rendered.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TemplateMode {
+    /// A missing field fails the action, which is then handled by the transition's error
+    /// policy.
+    #[default]
+    Strict,
+    /// A missing field renders as an empty string, or as `null` when it makes up a whole
+    /// parameter value.
+    Lenient,
+}
+
+/// A piece of a parsed template.
+#[derive(Debug, PartialEq)]
+enum Segment<'a> {
+    /// Literal text.
+    Text(&'a str),
+    /// A placeholder: its expression and the path it refers to.
+    Field(&'a str, FieldPath),
+}
+
+/// A parsed template.
+#[derive(Debug, PartialEq)]
+pub(crate) struct Template<'a> {
+    segments: Vec<Segment<'a>>,
+}
+
+impl<'a> Template<'a> {
+    /// Parses a template, reporting unterminated or malformed placeholders. Only `{{` followed
+    /// by `memory` or `event` opens a placeholder; any other `{{` is literal text.
+    pub(crate) fn parse(text: &'a str) -> Result<Self, String> {
+        let mut segments = Vec::new();
+        let mut rest = text;
+        while let Some(start) = rest.find("{{") {
+            let after_open = &rest[start + 2..];
+            if Self::source(after_open.trim_start()).is_none() {
+                segments.push(Segment::Text(&rest[..start + 2]));
+                rest = after_open;
+                continue;
+            }
+            if start > 0 {
+                segments.push(Segment::Text(&rest[..start]));
+            }
+            let end = after_open
+                .find("}}")
+                .ok_or_else(|| format!("unterminated placeholder in '{}'", text))?;
+            let expression = after_open[..end].trim();
+            let Some((event, path)) = Self::source(expression) else {
+                unreachable!("the placeholder starts with its source")
+            };
+            let path = FieldPath::parse_in(path.strip_prefix('.').unwrap_or(path), event)
+                .and_then(|path| path.check_single().map(|()| path))
+                .map_err(|message| format!("placeholder '{{{{{}}}}}': {}", expression, message))?;
+            segments.push(Segment::Field(expression, path));
+            rest = &after_open[end + 2..];
+        }
+        if !rest.is_empty() {
+            segments.push(Segment::Text(rest));
+        }
+        Ok(Template { segments })
+    }
+
+    /// Splits the text of a placeholder into its source, `true` for the event payload, and the
+    /// path that follows it. Returns `None` if the text does not start with `memory` or `event`
+    /// followed by a dotted path or a JSON Pointer.
+    fn source(text: &str) -> Option<(bool, &str)> {
+        [("memory", false), ("event", true)]
+            .into_iter()
+            .find_map(|(name, event)| {
+                let path = text.strip_prefix(name)?;
+                path.starts_with(['.', '/']).then_some((event, path))
+            })
+    }
+
+    /// Renders the template to a string. Strings are inserted as is and other values as JSON.
+    pub(crate) fn render(
+        &self,
+        memory: &Map<String, Value>,
+        payload: &Value,
+        mode: TemplateMode,
+    ) -> Result<String, String> {
+        let mut rendered = String::new();
+        for segment in &self.segments {
+            match segment {
+                Segment::Text(text) => rendered.push_str(text),
+                Segment::Field(expression, path) => {
+                    match Self::lookup(expression, path, memory, payload, mode)? {
+                        Some(Value::String(text)) => rendered.push_str(text),
+                        Some(value) => rendered.push_str(&value.to_string()),
+                        None => {}
+                    }
+                }
+            }
+        }
+        Ok(rendered)
+    }
+
+    /// Renders the template to a JSON value. A template made of a single placeholder yields the
+    /// field's value with its type preserved; anything else renders to a string.
+    pub(crate) fn render_value(
+        &self,
+        memory: &Map<String, Value>,
+        payload: &Value,
+        mode: TemplateMode,
+    ) -> Result<Value, String> {
+        if let [Segment::Field(expression, path)] = self.segments.as_slice() {
+            let value = Self::lookup(expression, path, memory, payload, mode)?;
+            return Ok(value.cloned().unwrap_or(Value::Null));
+        }
+        self.render(memory, payload, mode).map(Value::String)
+    }
+
+    /// Returns whether the template contains any placeholder.
+    pub(crate) fn has_placeholders(&self) -> bool {
+        self.segments
+            .iter()
+            .any(|segment| matches!(segment, Segment::Field(..)))
+    }
+
+    /// Resolves a placeholder, failing in strict mode if the field is missing.
+    fn lookup<'v>(
+        expression: &str,
+        path: &FieldPath,
+        memory: &'v Map<String, Value>,
+        payload: &'v Value,
+        mode: TemplateMode,
+    ) -> Result<Option<&'v Value>, String> {
+        match (path.get(memory, payload)?, mode) {
+            (None, TemplateMode::Strict) => Err(format!("field '{}' is missing", expression)),
+            (value, _) => Ok(value),
+        }
+    }
+}
+
+/// Checks every template in a JSON value (strings, nested in arrays and objects).
+pub(crate) fn parse_value(value: &Value) -> Result<(), String> {
+    match value {
+        Value::String(text) => Template::parse(text).map(|_| ()),
+        Value::Array(items) => items.iter().try_for_each(parse_value),
+        Value::Object(fields) => fields.values().try_for_each(parse_value),
+        _ => Ok(()),
+    }
+}
+
+/// Returns whether any string in a JSON value (nested in arrays and objects) has a placeholder.
+pub(crate) fn has_placeholders(value: &Value) -> bool {
+    match value {
+        Value::String(text) => Template::parse(text).is_ok_and(|t| t.has_placeholders()),
+        Value::Array(items) => items.iter().any(has_placeholders),
+        Value::Object(fields) => fields.values().any(has_placeholders),
+        _ => false,
+    }
+}
+
+/// Renders every template in a JSON value (strings, nested in arrays and objects).
+pub(crate) fn render_value(
+    value: &Value,
+    memory: &Map<String, Value>,
+    payload: &Value,
+    mode: TemplateMode,
+) -> Result<Value, String> {
+    match value {
+        Value::String(text) => {
+            let template = Template::parse(text)?;
+            if template.has_placeholders() {
+                template.render_value(memory, payload, mode)
+            } else {
+                Ok(value.clone())
+            }
+        }
+        Value::Array(items) => items
+            .iter()
+            .map(|item| render_value(item, memory, payload, mode))
+            .collect::<Result<_, _>>()
+            .map(Value::Array),
+        Value::Object(fields) => fields
+            .iter()
+            .map(|(key, field)| Ok((key.clone(), render_value(field, memory, payload, mode)?)))
+            .collect::<Result<_, String>>()
+            .map(Value::Object),
+        _ => Ok(value.clone()),
+    }
+}
@@ -1,6 +1,11 @@
 //! This module contains tests for the state machine implementation.
 use serde_json::{Map, Value};
-use stateflow::{Action, StateMachine};
+use stateflow::{
+    Action, ActionError, Clock, Error, HandlerRegistry, JournalTrigger, ManualClock, MemoryStore,
+    Snapshot, StateMachine, StateStore, TemplateMode, ValidationMode,
+};
+use std::sync::Arc;
+use std::time::Duration;
 
 /// Context struct used in the tests.
 struct Context {}
@@ -10,7 +15,7 @@ async fn test_action_handler_for_complex(
     action: &Action,
     _memory: &mut Map<String, Value>,
     _context: &mut Context,
-) {
+) -> Result<(), ActionError> {
     println!(
         "Test executing action: Type: {}, Command: {}",
         action.action_type, action.command
@@ -18,6 +23,7 @@ async fn test_action_handler_for_complex(
     // Optionally modify the memory if needed
     // For example:
     // memory.insert("last_action".to_string(), Value::String(action.command.clone()));
+    Ok(())
 }
 
 #[tokio::test]
@@ -189,9 +195,9 @@ async fn test_complex_state_machine() {
     let state_machine = StateMachine::new(
         json_config,
         Some("Idle".to_string()),
-        move |action, memory, context| {
+        HandlerRegistry::new().with_fallback(move |action, memory, context, _payload| {
             Box::pin(test_action_handler_for_complex(action, memory, context))
-        },
+        }),
         memory,
         Context {},
     )
@@ -237,7 +243,7 @@ async fn test_action_handler(
     action: &Action,
     memory: &mut Map<String, Value>,
     _context: &mut Context,
-) {
+) -> Result<(), ActionError> {
     println!(
         "Test executing action: Type: {}, Command: {}",
         action.action_type, action.command
@@ -252,6 +258,7 @@ async fn test_action_handler(
             *num = (num.as_i64().unwrap_or(0) + 1).into();
         }
     }
+    Ok(())
 }
 
 /// Test the basic functionality of the state machine with transitions.
@@ -291,7 +298,9 @@ async fn test_basic_transitions() {
     let state_machine = StateMachine::new(
         json_config,
         Some("A".to_string()),
-        |action, memory, context| Box::pin(test_action_handler(action, memory, context)),
+        HandlerRegistry::new().with_fallback(|action, memory, context, _payload| {
+            Box::pin(test_action_handler(action, memory, context))
+        }),
         memory,
         Context {},
     )
@@ -360,7 +369,9 @@ async fn test_state_validations() {
     let state_machine = StateMachine::new(
         json_config,
         Some("Start".to_string()),
-        |action, memory, context| Box::pin(test_action_handler(action, memory, context)),
+        HandlerRegistry::new().with_fallback(|action, memory, context, _payload| {
+            Box::pin(test_action_handler(action, memory, context))
+        }),
         memory,
         Context {},
     )
@@ -432,7 +443,9 @@ async fn test_transition_validations() {
     let state_machine = StateMachine::new(
         json_config,
         Some("Init".to_string()),
-        |action, memory, context| Box::pin(test_action_handler(action, memory, context)),
+        HandlerRegistry::new().with_fallback(|action, memory, context, _payload| {
+            Box::pin(test_action_handler(action, memory, context))
+        }),
         memory,
         Context {},
     )
@@ -512,7 +525,9 @@ async fn test_conditional_validations() {
     let state_machine = StateMachine::new(
         json_config,
         Some("Form".to_string()),
-        |action, memory, context| Box::pin(test_action_handler(action, memory, context)),
+        HandlerRegistry::new().with_fallback(|action, memory, context, _payload| {
+            Box::pin(test_action_handler(action, memory, context))
+        }),
         memory,
         Context {},
     )
@@ -601,7 +616,9 @@ async fn test_context_manipulation() {
     let state_machine = StateMachine::new(
         json_config,
         Some("Init".to_string()),
-        |action, memory, context| Box::pin(test_action_handler(action, memory, context)),
+        HandlerRegistry::new().with_fallback(|action, memory, context, _payload| {
+            Box::pin(test_action_handler(action, memory, context))
+        }),
         memory,
         Context {},
     )
@@ -658,7 +675,9 @@ fn test_invalid_configuration() {
     let result = StateMachine::new(
         invalid_json_config,
         None,
-        |action, memory, context| Box::pin(test_action_handler(action, memory, context)),
+        HandlerRegistry::new().with_fallback(|action, memory, context, _payload| {
+            Box::pin(test_action_handler(action, memory, context))
+        }),
         memory,
         Context {},
     );
@@ -705,7 +724,9 @@ async fn test_state_persistence() {
     let state_machine = StateMachine::new(
         json_config,
         Some("First".to_string()),
-        |action, memory, context| Box::pin(test_action_handler(action, memory, context)),
+        HandlerRegistry::new().with_fallback(|action, memory, context, _payload| {
+            Box::pin(test_action_handler(action, memory, context))
+        }),
         memory,
         Context {},
     )
@@ -725,7 +746,9 @@ async fn test_state_persistence() {
     let new_state_machine = StateMachine::new(
         json_config,
         Some(current_state.clone()),
-        |action, memory, context| Box::pin(test_action_handler(action, memory, context)),
+        HandlerRegistry::new().with_fallback(|action, memory, context, _payload| {
+            Box::pin(test_action_handler(action, memory, context))
+        }),
         Map::new(),
         Context {},
     )
@@ -748,7 +771,7 @@ async fn context_action_handler(
     action: &Action,
     _memory: &mut Map<String, Value>,
     context: &mut MyContext,
-) {
+) -> Result<(), ActionError> {
     println!(
         "Executing action: Type: {}, Command: {}",
         action.action_type, action.command
@@ -758,6 +781,7 @@ async fn context_action_handler(
     } else if action.action_type == "reset_counter" {
         context.counter = 0;
     }
+    Ok(())
 }
 
 /// Test case for testing the context usage in the state machine.
@@ -837,7 +861,9 @@ async fn test_context_usage() {
     let state_machine = StateMachine::new(
         json_config,
         Some("Init".to_string()),
-        |action, memory, context| Box::pin(context_action_handler(action, memory, context)),
+        HandlerRegistry::new().with_fallback(|action, memory, context, _payload| {
+            Box::pin(context_action_handler(action, memory, context))
+        }),
         memory,
         context,
     )
@@ -914,3 +940,2799 @@ async fn test_context_usage() {
         );
     }
 }
+
+/// Test that errors carry structured data and stable codes.
+#[tokio::test]
+async fn test_structured_errors() {
+    let json_config = r#"
+    {
+        "states": [
+            {
+                "name": "Start",
+                "validations": [
+                    {
+                        "field": "age",
+                        "rules": [
+                            { "type": "min_value", "value": 18 }
+                        ]
+                    }
+                ]
+            },
+            {
+                "name": "End"
+            }
+        ],
+        "transitions": [
+            {
+                "from": "Start",
+                "event": "proceed",
+                "to": "End"
+            }
+        ]
+    }
+    "#;
+
+    let mut memory = Map::new();
+    memory.insert("age".to_string(), Value::Number(16.into()));
+
+    let state_machine = StateMachine::new(
+        json_config,
+        None,
+        HandlerRegistry::new().with_fallback(|action, memory, context, _payload| {
+            Box::pin(test_action_handler(action, memory, context))
+        }),
+        memory,
+        Context {},
+    )
+    .expect("Failed to initialize state machine");
+
+    // Unknown events report the event and the current state
+    let err = state_machine.trigger("unknown").await.unwrap_err();
+    assert_eq!(err.code(), "no_transition");
+    assert_eq!(
+        err,
+        Error::NoTransition {
+            event: "unknown".to_string(),
+            state: "Start".to_string(),
+        }
+    );
+
+    // Validation failures report the field and the rule that failed
+    let err = state_machine.trigger("proceed").await.unwrap_err();
+    assert_eq!(err.code(), "validation_failed");
+    match err {
+        Error::Validation(violation) => {
+            assert_eq!(violation.field, "age");
+            assert_eq!(violation.rule, "min_value");
+        }
+        other => panic!("Unexpected error: {}", other),
+    }
+
+    // Configuration errors are distinguished by kind
+    let err = StateMachine::new(
+        "{ not json",
+        None,
+        HandlerRegistry::new().with_fallback(|action, memory, context, _payload| {
+            Box::pin(test_action_handler(action, memory, context))
+        }),
+        Map::new(),
+        Context {},
+    )
+    .err()
+    .expect("Invalid JSON was accepted");
+    assert_eq!(err.code(), "config_parse");
+
+    let err = StateMachine::new(
+        r#"{ "states": [{ "name": "A" }], "transitions": [{ "from": "A", "event": "go", "to": "B" }] }"#,
+        None,
+        HandlerRegistry::new().with_fallback(|action, memory, context, _payload| Box::pin(test_action_handler(action, memory, context))),
+        Map::new(),
+        Context {},
+    )
+    .err()
+    .expect("Transition to an undefined state was accepted");
+    assert_eq!(err, Error::UnknownState("B".to_string()));
+}
+
+/// An action handler that records every executed command in the `log` memory field.
+async fn logging_action_handler(
+    action: &Action,
+    memory: &mut Map<String, Value>,
+    _context: &mut Context,
+) -> Result<(), ActionError> {
+    let log = memory
+        .entry("log")
+        .or_insert_with(|| Value::Array(Vec::new()));
+    if let Value::Array(entries) = log {
+        entries.push(Value::String(action.command.clone()));
+    }
+    Ok(())
+}
+
+/// Test hierarchical states: inherited transitions and entry/exit ordering.
+#[tokio::test]
+async fn test_hierarchical_states() {
+    let json_config = r#"
+    {
+        "states": [
+            {
+                "name": "Active",
+                "initial": "Pending",
+                "on_enter_actions": [{ "action_type": "log", "command": "enter Active" }],
+                "on_exit_actions": [{ "action_type": "log", "command": "exit Active" }]
+            },
+            {
+                "name": "Pending",
+                "parent": "Active",
+                "on_enter_actions": [{ "action_type": "log", "command": "enter Pending" }],
+                "on_exit_actions": [{ "action_type": "log", "command": "exit Pending" }]
+            },
+            {
+                "name": "Processing",
+                "parent": "Active",
+                "on_enter_actions": [{ "action_type": "log", "command": "enter Processing" }],
+                "on_exit_actions": [{ "action_type": "log", "command": "exit Processing" }]
+            },
+            {
+                "name": "Cancelled",
+                "on_enter_actions": [{ "action_type": "log", "command": "enter Cancelled" }]
+            }
+        ],
+        "transitions": [
+            {
+                "from": "Pending",
+                "event": "process",
+                "to": "Processing",
+                "actions": [{ "action_type": "log", "command": "process" }]
+            },
+            {
+                "from": "Active",
+                "event": "cancel",
+                "to": "Cancelled",
+                "actions": [{ "action_type": "log", "command": "cancel" }]
+            },
+            {
+                "from": "Cancelled",
+                "event": "reopen",
+                "to": "Active"
+            }
+        ]
+    }
+    "#;
+
+    let state_machine = StateMachine::new(
+        json_config,
+        None,
+        HandlerRegistry::new().with_fallback(|action, memory, context, _payload| {
+            Box::pin(logging_action_handler(action, memory, context))
+        }),
+        Map::new(),
+        Context {},
+    )
+    .expect("Failed to initialize state machine");
+
+    // The first top-level state is entered through its initial child
+    assert_eq!(state_machine.get_current_state().await.unwrap(), "Pending");
+    assert_eq!(
+        state_machine.get_active_path().await.unwrap(),
+        vec!["Active", "Pending"]
+    );
+
+    // A transition between siblings does not exit the parent
+    state_machine.trigger("process").await.unwrap();
+    assert_eq!(
+        state_machine.get_active_path().await.unwrap(),
+        vec!["Active", "Processing"]
+    );
+
+    // A transition declared on the parent applies to its children
+    state_machine.trigger("cancel").await.unwrap();
+    assert_eq!(
+        state_machine.get_active_path().await.unwrap(),
+        vec!["Cancelled"]
+    );
+
+    // Targeting a compound state enters its initial child
+    state_machine.trigger("reopen").await.unwrap();
+    assert_eq!(state_machine.get_current_state().await.unwrap(), "Pending");
+
+    let memory = state_machine.memory.read().await;
+    assert_eq!(
+        memory.get("log").unwrap(),
+        &serde_json::json!([
+            "exit Pending",
+            "process",
+            "enter Processing",
+            "exit Processing",
+            "exit Active",
+            "cancel",
+            "enter Cancelled",
+            "enter Active",
+            "enter Pending"
+        ])
+    );
+}
+
+/// Test that invalid hierarchies are rejected.
+#[test]
+fn test_invalid_hierarchy() {
+    let cyclic_config = r#"
+    {
+        "states": [
+            { "name": "A", "parent": "B" },
+            { "name": "B", "parent": "A" }
+        ],
+        "transitions": []
+    }
+    "#;
+    let result = StateMachine::new(
+        cyclic_config,
+        None,
+        HandlerRegistry::new().with_fallback(|action, memory, context, _payload| {
+            Box::pin(test_action_handler(action, memory, context))
+        }),
+        Map::new(),
+        Context {},
+    );
+    assert_eq!(result.err().map(|e| e.code()), Some("invalid_config"));
+
+    let unknown_parent_config = r#"
+    {
+        "states": [
+            { "name": "A", "parent": "Missing" }
+        ],
+        "transitions": []
+    }
+    "#;
+    let result = StateMachine::new(
+        unknown_parent_config,
+        None,
+        HandlerRegistry::new().with_fallback(|action, memory, context, _payload| {
+            Box::pin(test_action_handler(action, memory, context))
+        }),
+        Map::new(),
+        Context {},
+    );
+    assert_eq!(
+        result.err(),
+        Some(Error::UnknownState("Missing".to_string()))
+    );
+}
+
+/// Test parallel states: every region is active and receives each event.
+#[tokio::test]
+async fn test_parallel_regions() {
+    let json_config = r#"
+    {
+        "states": [
+            {
+                "name": "Device",
+                "parallel": true,
+                "on_enter_actions": [{ "action_type": "log", "command": "enter Device" }],
+                "on_exit_actions": [{ "action_type": "log", "command": "exit Device" }]
+            },
+            { "name": "Power", "parent": "Device" },
+            { "name": "Off", "parent": "Power" },
+            { "name": "On", "parent": "Power" },
+            { "name": "Connectivity", "parent": "Device" },
+            { "name": "Offline", "parent": "Connectivity" },
+            { "name": "Online", "parent": "Connectivity" },
+            { "name": "Broken" }
+        ],
+        "transitions": [
+            { "from": "Off", "event": "power_on", "to": "On" },
+            { "from": "Offline", "event": "connect", "to": "Online" },
+            { "from": "On", "event": "sleep", "to": "Off" },
+            { "from": "Online", "event": "sleep", "to": "Offline" },
+            { "from": "Device", "event": "fail", "to": "Broken" },
+            { "from": "Broken", "event": "repair", "to": "Online" }
+        ]
+    }
+    "#;
+
+    let state_machine = StateMachine::new(
+        json_config,
+        None,
+        HandlerRegistry::new().with_fallback(|action, memory, context, _payload| {
+            Box::pin(logging_action_handler(action, memory, context))
+        }),
+        Map::new(),
+        Context {},
+    )
+    .expect("Failed to initialize state machine");
+
+    // Entering a parallel state enters the initial state of every region
+    assert_eq!(
+        state_machine.get_active_configuration().await.unwrap(),
+        vec!["Device", "Power", "Off", "Connectivity", "Offline"]
+    );
+
+    // Events handled by a single region leave the other regions untouched
+    state_machine.trigger("power_on").await.unwrap();
+    state_machine.trigger("connect").await.unwrap();
+    assert_eq!(
+        state_machine.get_active_configuration().await.unwrap(),
+        vec!["Device", "Power", "On", "Connectivity", "Online"]
+    );
+
+    // A single event is dispatched to every active region
+    state_machine.trigger("sleep").await.unwrap();
+    assert_eq!(
+        state_machine.get_active_configuration().await.unwrap(),
+        vec!["Device", "Power", "Off", "Connectivity", "Offline"]
+    );
+
+    // An event no region handles is rejected
+    assert_eq!(
+        state_machine.trigger("unknown").await.unwrap_err().code(),
+        "no_transition"
+    );
+
+    // A transition declared on the parallel state exits every region exactly once
+    state_machine.trigger("fail").await.unwrap();
+    assert_eq!(
+        state_machine.get_active_configuration().await.unwrap(),
+        vec!["Broken"]
+    );
+
+    // Targeting a state inside one region enters the other regions at their initial state
+    state_machine.trigger("repair").await.unwrap();
+    assert_eq!(
+        state_machine.get_active_configuration().await.unwrap(),
+        vec!["Device", "Power", "Off", "Connectivity", "Online"]
+    );
+
+    let memory = state_machine.memory.read().await;
+    assert_eq!(
+        memory.get("log").unwrap(),
+        &serde_json::json!(["exit Device", "enter Device"])
+    );
+    drop(memory);
+
+    // A snapshot must have one state active in every region, and only one per region
+    for leaves in [vec!["Off"], vec!["Off", "On", "Offline"]] {
+        let mut snapshot = state_machine.snapshot().await;
+        snapshot.current_state = leaves.into_iter().map(String::from).collect();
+        let result = StateMachine::restore(
+            json_config,
+            snapshot,
+            HandlerRegistry::new().with_fallback(|action, memory, context, _payload| {
+                Box::pin(logging_action_handler(action, memory, context))
+            }),
+            Context {},
+        );
+        assert_eq!(result.err().unwrap().code(), "snapshot_mismatch");
+    }
+}
+
+/// Test guarded transitions: the first candidate whose guard holds is taken.
+#[tokio::test]
+async fn test_guarded_transitions() {
+    let json_config = r#"
+    {
+        "states": [
+            { "name": "Draft" },
+            { "name": "AutoApproved" },
+            { "name": "ManualReview" }
+        ],
+        "transitions": [
+            {
+                "from": "Draft",
+                "event": "submit",
+                "to": "AutoApproved",
+                "guard": { "field": "amount", "operator": "<", "value": 100 }
+            },
+            {
+                "from": "Draft",
+                "event": "submit",
+                "to": "ManualReview"
+            }
+        ]
+    }
+    "#;
+
+    for (amount, expected_state) in [(50, "AutoApproved"), (500, "ManualReview")] {
+        let mut memory = Map::new();
+        memory.insert("amount".to_string(), Value::Number(amount.into()));
+
+        let state_machine = StateMachine::new(
+            json_config,
+            None,
+            HandlerRegistry::new().with_fallback(|action, memory, context, _payload| {
+                Box::pin(test_action_handler(action, memory, context))
+            }),
+            memory,
+            Context {},
+        )
+        .expect("Failed to initialize state machine");
+
+        state_machine.trigger("submit").await.unwrap();
+        assert_eq!(
+            state_machine.get_current_state().await.unwrap(),
+            expected_state
+        );
+    }
+}
+
+/// Test that transitions shadowed by an earlier unguarded transition are rejected.
+#[test]
+fn test_duplicate_transitions_rejected() {
+    let json_config = r#"
+    {
+        "states": [
+            { "name": "A" },
+            { "name": "B" },
+            { "name": "C" }
+        ],
+        "transitions": [
+            { "from": "A", "event": "go", "to": "B" },
+            { "from": "A", "event": "go", "to": "C" }
+        ]
+    }
+    "#;
+
+    let result = StateMachine::new(
+        json_config,
+        None,
+        HandlerRegistry::new().with_fallback(|action, memory, context, _payload| {
+            Box::pin(test_action_handler(action, memory, context))
+        }),
+        Map::new(),
+        Context {},
+    );
+    assert_eq!(result.err().map(|e| e.code()), Some("invalid_config"));
+}
+
+/// An action handler that copies the event payload into memory.
+async fn payload_action_handler(
+    action: &Action,
+    memory: &mut Map<String, Value>,
+    _context: &mut Context,
+    payload: &Value,
+) -> Result<(), ActionError> {
+    if action.action_type == "store_payload" {
+        memory.insert(action.command.clone(), payload.clone());
+    }
+    Ok(())
+}
+
+/// Test event payloads in guards, validations, actions and payload schemas.
+#[tokio::test]
+async fn test_event_payloads() {
+    let json_config = r#"
+    {
+        "states": [
+            { "name": "Cart" },
+            { "name": "Paid" },
+            { "name": "Review" }
+        ],
+        "transitions": [
+            {
+                "from": "Cart",
+                "event": "pay",
+                "to": "Paid",
+                "guard": { "field": "event.amount", "operator": "<", "value": 100 },
+                "actions": [{ "action_type": "store_payload", "command": "payment" }],
+                "validations": [
+                    {
+                        "field": "event.currency",
+                        "rules": [{ "type": "enum", "values": ["EUR", "USD"] }]
+                    }
+                ]
+            },
+            { "from": "Cart", "event": "pay", "to": "Review" }
+        ],
+        "event_schemas": {
+            "pay": {
+                "type": "object",
+                "required": ["amount", "currency"],
+                "properties": {
+                    "amount": { "type": "number" },
+                    "currency": { "type": "string" }
+                }
+            }
+        }
+    }
+    "#;
+
+    let state_machine = StateMachine::new(
+        json_config,
+        None,
+        HandlerRegistry::new().with_fallback(|action, memory, context, payload| {
+            Box::pin(payload_action_handler(action, memory, context, payload))
+        }),
+        Map::new(),
+        Context {},
+    )
+    .expect("Failed to initialize state machine");
+
+    // Payloads that do not match the event schema are rejected up front
+    let err = state_machine
+        .trigger_with("pay", serde_json::json!({ "amount": "ten" }))
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), "invalid_payload");
+    let err = state_machine.trigger("pay").await.unwrap_err();
+    assert_eq!(err.code(), "invalid_payload");
+
+    // Validations can refer to payload fields
+    let err = state_machine
+        .trigger_with(
+            "pay",
+            serde_json::json!({ "amount": 10, "currency": "GBP" }),
+        )
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), "validation_failed");
+    assert_eq!(state_machine.get_current_state().await.unwrap(), "Cart");
+
+    // Guards can refer to payload fields, and actions receive the payload
+    let payload = serde_json::json!({ "amount": 10, "currency": "EUR" });
+    state_machine
+        .trigger_with("pay", payload.clone())
+        .await
+        .unwrap();
+    assert_eq!(state_machine.get_current_state().await.unwrap(), "Paid");
+    assert_eq!(
+        state_machine.memory.read().await.get("payment"),
+        Some(&payload)
+    );
+}
+
+/// An action handler that fails for `fail` actions and logs every other command.
+async fn failing_action_handler(
+    action: &Action,
+    memory: &mut Map<String, Value>,
+    context: &mut Context,
+) -> Result<(), ActionError> {
+    if action.action_type == "fail" {
+        return Err(ActionError::new(format!("{} failed", action.command)));
+    }
+    logging_action_handler(action, memory, context).await
+}
+
+/// Test the abort, continue and redirect error policies of transitions.
+#[tokio::test]
+async fn test_action_error_policies() {
+    let json_config = r#"
+    {
+        "states": [
+            {
+                "name": "Checkout",
+                "on_exit_actions": [{ "action_type": "log", "command": "exit Checkout" }]
+            },
+            {
+                "name": "Paid",
+                "on_enter_actions": [{ "action_type": "log", "command": "enter Paid" }]
+            },
+            {
+                "name": "PaymentFailed",
+                "on_enter_actions": [{ "action_type": "log", "command": "enter PaymentFailed" }]
+            }
+        ],
+        "transitions": [
+            {
+                "from": "Checkout",
+                "event": "pay",
+                "to": "Paid",
+                "actions": [
+                    { "action_type": "log", "command": "charge" },
+                    { "action_type": "fail", "command": "capture" }
+                ]
+            },
+            {
+                "from": "Checkout",
+                "event": "pay_lenient",
+                "to": "Paid",
+                "error_policy": "continue",
+                "actions": [
+                    { "action_type": "fail", "command": "capture" },
+                    { "action_type": "log", "command": "charge" }
+                ]
+            },
+            {
+                "from": "Checkout",
+                "event": "pay_or_fail",
+                "to": "Paid",
+                "error_policy": "redirect",
+                "on_error": "PaymentFailed",
+                "actions": [
+                    { "action_type": "log", "command": "charge" },
+                    { "action_type": "fail", "command": "capture" }
+                ]
+            }
+        ]
+    }
+    "#;
+
+    let new_machine = || {
+        StateMachine::new(
+            json_config,
+            None,
+            HandlerRegistry::new().with_fallback(|action, memory, context, _payload| {
+                Box::pin(failing_action_handler(action, memory, context))
+            }),
+            Map::new(),
+            Context {},
+        )
+        .expect("Failed to initialize state machine")
+    };
+
+    // Abort: the error is returned and the memory and state are rolled back
+    let state_machine = new_machine();
+    let err = state_machine.trigger("pay").await.unwrap_err();
+    assert_eq!(
+        err,
+        Error::ActionFailed {
+            action_type: "fail".to_string(),
+            message: "capture failed".to_string(),
+        }
+    );
+    assert_eq!(state_machine.get_current_state().await.unwrap(), "Checkout");
+    assert!(state_machine.memory.read().await.get("log").is_none());
+
+    // Continue: the failure is skipped and the transition completes
+    let state_machine = new_machine();
+    state_machine.trigger("pay_lenient").await.unwrap();
+    assert_eq!(state_machine.get_current_state().await.unwrap(), "Paid");
+    assert_eq!(
+        state_machine.memory.read().await.get("log").unwrap(),
+        &serde_json::json!(["exit Checkout", "charge", "enter Paid"])
+    );
+
+    // Redirect: the error is returned and the machine moves to the on_error state
+    let state_machine = new_machine().with_journal();
+    let err = state_machine.trigger("pay_or_fail").await.unwrap_err();
+    assert_eq!(err.code(), "action_failed");
+    assert_eq!(
+        state_machine.get_current_state().await.unwrap(),
+        "PaymentFailed"
+    );
+    assert_eq!(
+        state_machine.memory.read().await.get("log").unwrap(),
+        &serde_json::json!(["exit Checkout", "charge", "enter PaymentFailed"])
+    );
+
+    // The redirected step is journaled with its error and replays to the on_error state
+    let journal = state_machine.journal().await.unwrap();
+    assert_eq!(journal.entries.len(), 1);
+    assert_eq!(journal.entries[0].error, Some(err.to_string()));
+    let report = StateMachine::replay(json_config, &journal).await.unwrap();
+    assert!(report.divergences.is_empty());
+    assert_eq!(report.snapshot, state_machine.snapshot().await);
+}
+
+/// Test timed transitions: scheduled on entry, cancelled on exit and fired by the clock.
+#[tokio::test]
+async fn test_timed_transitions() {
+    let json_config = r#"
+    {
+        "states": [
+            {
+                "name": "PendingApproval",
+                "on_enter_actions": [{ "action_type": "log", "command": "enter PendingApproval" }],
+                "after": [
+                    {
+                        "duration_ms": 172800000,
+                        "to": "Escalated",
+                        "actions": [{ "action_type": "log", "command": "escalate" }]
+                    }
+                ]
+            },
+            { "name": "Approved" },
+            { "name": "Escalated" }
+        ],
+        "transitions": [
+            { "from": "PendingApproval", "event": "approve", "to": "Approved" },
+            { "from": "Approved", "event": "reopen", "to": "PendingApproval" }
+        ]
+    }
+    "#;
+
+    let clock = Arc::new(ManualClock::new(1_000));
+    let state_machine = StateMachine::new(
+        json_config,
+        None,
+        HandlerRegistry::new().with_fallback(|action, memory, context, _payload| {
+            Box::pin(logging_action_handler(action, memory, context))
+        }),
+        Map::new(),
+        Context {},
+    )
+    .expect("Failed to initialize state machine")
+    .with_clock(clock.clone());
+
+    assert_eq!(state_machine.next_timer_deadline(), Some(172_801_000));
+
+    // Nothing is due before the delay has elapsed
+    clock.advance(Duration::from_secs(47 * 3600));
+    assert_eq!(state_machine.fire_due_timers().await.unwrap(), 0);
+    assert_eq!(
+        state_machine.get_current_state().await.unwrap(),
+        "PendingApproval"
+    );
+
+    // Leaving the state cancels its timer, and re-entering schedules a new one
+    state_machine.trigger("approve").await.unwrap();
+    assert_eq!(state_machine.next_timer_deadline(), None);
+    state_machine.trigger("reopen").await.unwrap();
+    let reentered_at = clock.now_ms();
+    assert_eq!(
+        state_machine.next_timer_deadline(),
+        Some(reentered_at + 172_800_000)
+    );
+
+    clock.advance(Duration::from_secs(48 * 3600));
+    assert_eq!(state_machine.fire_due_timers().await.unwrap(), 1);
+    assert_eq!(
+        state_machine.get_current_state().await.unwrap(),
+        "Escalated"
+    );
+    assert_eq!(state_machine.next_timer_deadline(), None);
+    assert_eq!(
+        state_machine.memory.read().await.get("log").unwrap(),
+        &serde_json::json!(["enter PendingApproval", "escalate"])
+    );
+}
+
+/// Test eventless transitions: routing states and the loop limit.
+#[tokio::test]
+async fn test_eventless_transitions() {
+    let json_config = r#"
+    {
+        "states": [
+            { "name": "Received" },
+            {
+                "name": "Routing",
+                "on_enter_actions": [{ "action_type": "log", "command": "enter Routing" }]
+            },
+            { "name": "Express" },
+            { "name": "Standard" },
+            { "name": "Ping" },
+            { "name": "Pong" }
+        ],
+        "transitions": [
+            { "from": "Received", "event": "route", "to": "Routing" },
+            {
+                "from": "Routing",
+                "to": "Express",
+                "guard": { "field": "priority", "operator": "==", "value": "high" },
+                "actions": [{ "action_type": "log", "command": "express" }]
+            },
+            { "from": "Routing", "to": "Standard" },
+            { "from": "Standard", "event": "ship", "to": "Received" },
+            { "from": "Received", "event": "loop", "to": "Ping" },
+            { "from": "Ping", "to": "Pong" },
+            { "from": "Pong", "to": "Ping" }
+        ]
+    }
+    "#;
+
+    let mut memory = Map::new();
+    memory.insert("priority".to_string(), Value::String("high".to_string()));
+    let state_machine = StateMachine::new(
+        json_config,
+        None,
+        HandlerRegistry::new().with_fallback(|action, memory, context, _payload| {
+            Box::pin(logging_action_handler(action, memory, context))
+        }),
+        memory,
+        Context {},
+    )
+    .expect("Failed to initialize state machine");
+
+    // The routing state is left as soon as its on-enter actions have run
+    state_machine.trigger("route").await.unwrap();
+    assert_eq!(state_machine.get_current_state().await.unwrap(), "Express");
+    assert_eq!(
+        state_machine.memory.read().await.get("log").unwrap(),
+        &serde_json::json!(["enter Routing", "express"])
+    );
+
+    // The unguarded fallback is taken when the guard does not hold
+    let mut memory = Map::new();
+    memory.insert("priority".to_string(), Value::String("low".to_string()));
+    let state_machine = StateMachine::new(
+        json_config,
+        None,
+        HandlerRegistry::new().with_fallback(|action, memory, context, _payload| {
+            Box::pin(logging_action_handler(action, memory, context))
+        }),
+        memory,
+        Context {},
+    )
+    .expect("Failed to initialize state machine");
+    state_machine.trigger("route").await.unwrap();
+    assert_eq!(state_machine.get_current_state().await.unwrap(), "Standard");
+
+    // A machine starting in a routing state leaves it with `settle`...
+    let mut memory = Map::new();
+    memory.insert("priority".to_string(), Value::String("high".to_string()));
+    let state_machine = StateMachine::new(
+        json_config,
+        Some("Routing".to_string()),
+        HandlerRegistry::new().with_fallback(|action, memory, context, _payload| {
+            Box::pin(logging_action_handler(action, memory, context))
+        }),
+        memory,
+        Context {},
+    )
+    .expect("Failed to initialize state machine");
+    state_machine.settle().await.unwrap();
+    assert_eq!(state_machine.get_current_state().await.unwrap(), "Express");
+
+    // ...or before its first event is handled
+    let mut memory = Map::new();
+    memory.insert("priority".to_string(), Value::String("low".to_string()));
+    let state_machine = StateMachine::new(
+        json_config,
+        Some("Routing".to_string()),
+        HandlerRegistry::new().with_fallback(|action, memory, context, _payload| {
+            Box::pin(logging_action_handler(action, memory, context))
+        }),
+        memory,
+        Context {},
+    )
+    .expect("Failed to initialize state machine");
+    state_machine.trigger("ship").await.unwrap();
+    assert_eq!(state_machine.get_current_state().await.unwrap(), "Received");
+
+    // A cycle of eventless transitions is stopped by the loop limit
+    let state_machine = StateMachine::new(
+        json_config,
+        None,
+        HandlerRegistry::new().with_fallback(|action, memory, context, _payload| {
+            Box::pin(logging_action_handler(action, memory, context))
+        }),
+        Map::new(),
+        Context {},
+    )
+    .expect("Failed to initialize state machine")
+    .with_max_eventless_steps(10);
+    let err = state_machine.trigger("loop").await.unwrap_err();
+    assert!(matches!(err, Error::EventlessLoop { limit: 10, .. }));
+}
+
+/// Test final states: rejected outgoing transitions, `is_done` and `wait_for_completion`.
+#[tokio::test]
+async fn test_final_states() {
+    let json_config = r#"
+    {
+        "states": [
+            { "name": "Processing" },
+            {
+                "name": "Completed",
+                "final": true,
+                "on_enter_actions": [{ "action_type": "log", "command": "enter Completed" }]
+            },
+            { "name": "Failed", "final": true }
+        ],
+        "transitions": [
+            { "from": "Processing", "event": "finish", "to": "Completed" },
+            { "from": "Processing", "event": "fail", "to": "Failed" }
+        ]
+    }
+    "#;
+
+    let state_machine = Arc::new(
+        StateMachine::new(
+            json_config,
+            None,
+            HandlerRegistry::new().with_fallback(|action, memory, context, _payload| {
+                Box::pin(logging_action_handler(action, memory, context))
+            }),
+            Map::new(),
+            Context {},
+        )
+        .expect("Failed to initialize state machine"),
+    );
+    assert!(!state_machine.is_done());
+
+    let waiter = {
+        let state_machine = state_machine.clone();
+        tokio::spawn(async move { state_machine.wait_for_completion().await })
+    };
+    tokio::task::yield_now().await;
+    state_machine.trigger("finish").await.unwrap();
+
+    let (final_state, memory) = waiter.await.unwrap();
+    assert_eq!(final_state, "Completed");
+    assert_eq!(
+        memory.get("log").unwrap(),
+        &serde_json::json!(["enter Completed"])
+    );
+    assert!(state_machine.is_done());
+
+    // Final states cannot have outgoing transitions
+    let invalid_config = r#"
+    {
+        "states": [
+            { "name": "Processing" },
+            { "name": "Completed", "final": true }
+        ],
+        "transitions": [
+            { "from": "Processing", "event": "finish", "to": "Completed" },
+            { "from": "Completed", "event": "restart", "to": "Processing" }
+        ]
+    }
+    "#;
+    let result = StateMachine::new(
+        invalid_config,
+        None,
+        HandlerRegistry::new().with_fallback(|action, memory, context, _payload| {
+            Box::pin(logging_action_handler(action, memory, context))
+        }),
+        Map::new(),
+        Context {},
+    );
+    assert_eq!(result.err().unwrap().code(), "invalid_config");
+}
+
+/// Test snapshots: memory, active states, timers and version survive a restore.
+#[tokio::test]
+async fn test_snapshot_restore() {
+    let json_config = r#"
+    {
+        "states": [
+            { "name": "Draft" },
+            {
+                "name": "PendingApproval",
+                "after": [{ "duration_ms": 1000, "to": "Escalated" }]
+            },
+            { "name": "Escalated" }
+        ],
+        "transitions": [
+            {
+                "from": "Draft",
+                "event": "submit",
+                "to": "PendingApproval",
+                "actions": [{ "action_type": "log", "command": "submitted" }]
+            }
+        ]
+    }
+    "#;
+
+    let clock = Arc::new(ManualClock::new(0));
+    let state_machine = StateMachine::new(
+        json_config,
+        None,
+        HandlerRegistry::new().with_fallback(|action, memory, context, _payload| {
+            Box::pin(logging_action_handler(action, memory, context))
+        }),
+        Map::new(),
+        Context {},
+    )
+    .expect("Failed to initialize state machine")
+    .with_clock(clock.clone());
+    state_machine.trigger("submit").await.unwrap();
+
+    // The snapshot round-trips through JSON
+    let snapshot = state_machine.snapshot().await;
+    assert_eq!(snapshot.current_state, vec!["PendingApproval".to_string()]);
+    assert_eq!(snapshot.version, 1);
+    let snapshot: Snapshot =
+        serde_json::from_str(&serde_json::to_string(&snapshot).unwrap()).unwrap();
+
+    let restored = StateMachine::restore(
+        json_config,
+        snapshot.clone(),
+        HandlerRegistry::new().with_fallback(|action, memory, context, _payload| {
+            Box::pin(logging_action_handler(action, memory, context))
+        }),
+        Context {},
+    )
+    .expect("Failed to restore state machine")
+    .with_clock(clock.clone());
+    assert_eq!(
+        restored.get_current_state().await.unwrap(),
+        "PendingApproval"
+    );
+    assert_eq!(restored.version(), 1);
+    assert_eq!(
+        restored.memory.read().await.get("log").unwrap(),
+        &serde_json::json!(["submitted"])
+    );
+
+    // The pending timer keeps its deadline
+    assert_eq!(restored.next_timer_deadline(), Some(1000));
+    clock.advance(Duration::from_millis(1000));
+    assert_eq!(restored.fire_due_timers().await.unwrap(), 1);
+    assert_eq!(restored.get_current_state().await.unwrap(), "Escalated");
+    assert_eq!(restored.version(), 2);
+
+    // Reformatting the configuration or reordering its keys keeps the snapshot valid
+    let reformatted = serde_json::to_string_pretty(&serde_json::json!({
+        "transitions": [{
+            "to": "PendingApproval",
+            "event": "submit",
+            "from": "Draft",
+            "actions": [{ "command": "submitted", "action_type": "log" }]
+        }],
+        "states": [
+            { "name": "Draft" },
+            {
+                "after": [{ "to": "Escalated", "duration_ms": 1000 }],
+                "name": "PendingApproval"
+            },
+            { "name": "Escalated" }
+        ]
+    }))
+    .unwrap();
+    StateMachine::restore(
+        &reformatted,
+        snapshot.clone(),
+        HandlerRegistry::new().with_fallback(|action, memory, context, _payload| {
+            Box::pin(logging_action_handler(action, memory, context))
+        }),
+        Context {},
+    )
+    .expect("Failed to restore state machine from the reformatted configuration");
+
+    // A snapshot must describe states the machine can be in, with timers of active states only
+    let mut two_states = snapshot.clone();
+    two_states.current_state = vec!["Draft".to_string(), "Escalated".to_string()];
+    let mut inactive_timer = snapshot.clone();
+    inactive_timer.current_state = vec!["Escalated".to_string()];
+    for invalid in [two_states, inactive_timer] {
+        let result = StateMachine::restore(
+            json_config,
+            invalid,
+            HandlerRegistry::new().with_fallback(|action, memory, context, _payload| {
+                Box::pin(logging_action_handler(action, memory, context))
+            }),
+            Context {},
+        );
+        assert_eq!(result.err().unwrap().code(), "snapshot_mismatch");
+    }
+
+    // A snapshot only fits the configuration it was taken from
+    let result = StateMachine::restore(
+        &json_config.replace("Escalated", "Rejected"),
+        snapshot,
+        HandlerRegistry::new().with_fallback(|action, memory, context, _payload| {
+            Box::pin(logging_action_handler(action, memory, context))
+        }),
+        Context {},
+    );
+    assert_eq!(result.err().unwrap().code(), "snapshot_mismatch");
+}
+
+/// Runs two machines on the same stored instance and checks that only one of them can advance
+/// it, and that a machine restored from the store picks up where the winner left off.
+async fn check_state_store(store: Arc<dyn StateStore>) {
+    let json_config = r#"
+    {
+        "states": [
+            { "name": "Draft" },
+            { "name": "Submitted" },
+            { "name": "Approved" }
+        ],
+        "transitions": [
+            {
+                "from": "Draft",
+                "event": "submit",
+                "to": "Submitted",
+                "actions": [{ "action_type": "log", "command": "submitted" }]
+            },
+            { "from": "Submitted", "event": "approve", "to": "Approved" }
+        ]
+    }
+    "#;
+    let new_machine = || {
+        StateMachine::new(
+            json_config,
+            None,
+            HandlerRegistry::new().with_fallback(|action, memory, context, _payload| {
+                Box::pin(logging_action_handler(action, memory, context))
+            }),
+            Map::new(),
+            Context {},
+        )
+        .expect("Failed to initialize state machine")
+        .with_store(store.clone(), "order-1")
+    };
+
+    let first = new_machine();
+    let second = new_machine();
+    first.trigger("submit").await.unwrap();
+    assert_eq!(store.load("order-1").unwrap().unwrap().version, 1);
+
+    // The second worker started from the same version and loses the race
+    let err = second.trigger("submit").await.unwrap_err();
+    assert_eq!(
+        err,
+        Error::VersionConflict {
+            instance_id: "order-1".to_string(),
+            expected_version: 0,
+        }
+    );
+    assert_eq!(second.get_current_state().await.unwrap(), "Draft");
+    assert!(second.memory.read().await.get("log").is_none());
+
+    // A restarted worker resumes from the stored snapshot
+    let snapshot = store.load("order-1").unwrap().unwrap();
+    let resumed = StateMachine::restore(
+        json_config,
+        snapshot,
+        HandlerRegistry::new().with_fallback(|action, memory, context, _payload| {
+            Box::pin(logging_action_handler(action, memory, context))
+        }),
+        Context {},
+    )
+    .expect("Failed to restore state machine")
+    .with_store(store.clone(), "order-1");
+    resumed.trigger("approve").await.unwrap();
+    let stored = store.load("order-1").unwrap().unwrap();
+    assert_eq!(stored.version, 2);
+    assert_eq!(stored.current_state, vec!["Approved".to_string()]);
+    assert_eq!(
+        stored.memory.get("log").unwrap(),
+        &serde_json::json!(["submitted"])
+    );
+}
+
+/// Test persistence with the in-memory store.
+#[tokio::test]
+async fn test_memory_store() {
+    check_state_store(Arc::new(MemoryStore::new())).await;
+}
+
+/// Test persistence with the JSON-file store.
+#[cfg(feature = "file-store")]
+#[tokio::test]
+async fn test_file_store() {
+    let directory = std::env::temp_dir().join(format!("stateflow-test-{}", std::process::id()));
+    let store = stateflow::FileStore::new(&directory).unwrap();
+    check_state_store(Arc::new(store.clone())).await;
+
+    // A lock left behind by a crashed writer is broken once it is stale
+    let snapshot = store.load("order-1").unwrap().unwrap();
+    let lock_path = directory.join("crashed.lock");
+    std::fs::write(&lock_path, "4242 1000 0").unwrap();
+    assert!(store.compare_and_swap("crashed", 0, &snapshot).unwrap());
+    assert!(!lock_path.exists());
+
+    // ...but a lock held by a live writer is not
+    let now_ms = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_millis();
+    std::fs::write(&lock_path, format!("4242 {} 0", now_ms)).unwrap();
+    let err = store.compare_and_swap("crashed", 1, &snapshot).unwrap_err();
+    assert_eq!(err.code(), "store_failed");
+    std::fs::remove_dir_all(directory).unwrap();
+}
+
+/// Test persistence with the SQLite store.
+#[cfg(feature = "sqlite-store")]
+#[tokio::test]
+async fn test_sqlite_store() {
+    let store = stateflow::SqliteStore::open_in_memory().unwrap();
+    check_state_store(Arc::new(store)).await;
+}
+
+/// Test the journal and its replay with actions turned off.
+#[tokio::test]
+async fn test_journal_replay() {
+    let json_config = r#"
+    {
+        "states": [
+            { "name": "Cart" },
+            {
+                "name": "Scoring",
+                "on_enter_actions": [{ "action_type": "store_payload", "command": "amount" }]
+            },
+            { "name": "Review" },
+            { "name": "Paid" }
+        ],
+        "transitions": [
+            { "from": "Cart", "event": "checkout", "to": "Scoring" },
+            {
+                "from": "Scoring",
+                "to": "Review",
+                "guard": { "field": "amount", "operator": ">=", "value": 1000 }
+            },
+            { "from": "Scoring", "to": "Paid" }
+        ]
+    }
+    "#;
+
+    let state_machine = StateMachine::new(
+        json_config,
+        None,
+        HandlerRegistry::new().with_fallback(|action, memory, context, payload| {
+            Box::pin(payload_action_handler(action, memory, context, payload))
+        }),
+        Map::new(),
+        Context {},
+    )
+    .expect("Failed to initialize state machine")
+    .with_journal();
+    state_machine
+        .trigger_with("checkout", serde_json::json!(2500))
+        .await
+        .unwrap();
+    assert_eq!(state_machine.get_current_state().await.unwrap(), "Review");
+
+    let journal = state_machine.journal().await.unwrap();
+    assert_eq!(journal.start.current_state, vec!["Cart".to_string()]);
+    assert_eq!(journal.entries.len(), 2);
+    assert_eq!(journal.entries[0].to, vec!["Scoring".to_string()]);
+    assert_eq!(
+        journal.entries[0].memory_diff.set.get("amount").unwrap(),
+        &serde_json::json!(2500)
+    );
+    assert_eq!(journal.entries[0].actions.len(), 1);
+    assert_eq!(journal.entries[1].trigger, JournalTrigger::Eventless);
+
+    // Replaying applies the recorded memory changes, so the guard takes the same branch
+    let report = StateMachine::replay(json_config, &journal).await.unwrap();
+    assert!(report.divergences.is_empty());
+    assert_eq!(report.snapshot, state_machine.snapshot().await);
+
+    // A tampered journal is flagged where the rebuilt state differs
+    let mut tampered = journal.clone();
+    tampered.entries[1].to = vec!["Paid".to_string()];
+    let report = StateMachine::replay(json_config, &tampered).await.unwrap();
+    assert_eq!(report.divergences.len(), 1);
+    assert_eq!(report.divergences[0].version, 2);
+    assert_eq!(report.divergences[0].replayed, vec!["Review".to_string()]);
+    assert_eq!(report.snapshot.current_state, vec!["Paid".to_string()]);
+}
+
+/// Test subscriptions: completed and rejected steps, and the current state watch channel.
+#[tokio::test]
+async fn test_subscriptions() {
+    let json_config = r#"
+    {
+        "states": [
+            { "name": "Idle" },
+            { "name": "Routing" },
+            { "name": "Running" }
+        ],
+        "transitions": [
+            { "from": "Idle", "event": "start", "to": "Routing" },
+            { "from": "Routing", "to": "Running" }
+        ]
+    }
+    "#;
+
+    let state_machine = StateMachine::new(
+        json_config,
+        None,
+        HandlerRegistry::new().with_fallback(|action, memory, context, _payload| {
+            Box::pin(test_action_handler(action, memory, context))
+        }),
+        Map::new(),
+        Context {},
+    )
+    .expect("Failed to initialize state machine");
+    let mut events = state_machine.subscribe();
+    let mut current_state = state_machine.watch_state();
+    assert_eq!(*current_state.borrow(), vec!["Idle".to_string()]);
+
+    // Rejected events are published with the error and an unchanged state
+    let err = state_machine.trigger("stop").await.unwrap_err();
+    let event = events.recv().await.unwrap();
+    assert_eq!(event.event(), Some("stop"));
+    assert_eq!(event.from, event.to);
+    assert_eq!(event.outcome, Err(err));
+    assert!(!current_state.has_changed().unwrap());
+
+    // Each step is published, including eventless rounds
+    state_machine.trigger("start").await.unwrap();
+    let event = events.recv().await.unwrap();
+    assert_eq!(event.event(), Some("start"));
+    assert_eq!(event.from, vec!["Idle".to_string()]);
+    assert_eq!(event.to, vec!["Routing".to_string()]);
+    assert!(event.outcome.is_ok());
+    let event = events.recv().await.unwrap();
+    assert_eq!(event.trigger, JournalTrigger::Eventless);
+    assert_eq!(event.to, vec!["Running".to_string()]);
+
+    assert!(current_state.has_changed().unwrap());
+    assert_eq!(
+        *current_state.borrow_and_update(),
+        vec!["Running".to_string()]
+    );
+}
+
+/// Test the handler registry: per-type dispatch, the fallback and uncovered action types.
+#[tokio::test]
+async fn test_handler_registry() {
+    let json_config = r#"
+    {
+        "states": [
+            {
+                "name": "Idle",
+                "on_exit_actions": [{ "action_type": "log", "command": "leaving Idle" }]
+            },
+            {
+                "name": "Running",
+                "on_enter_actions": [{ "action_type": "metric", "command": "runs" }]
+            }
+        ],
+        "transitions": [
+            { "from": "Idle", "event": "start", "to": "Running" }
+        ]
+    }
+    "#;
+
+    let handlers = HandlerRegistry::new()
+        .with_handler("log", |action, memory, context, _payload| {
+            Box::pin(logging_action_handler(action, memory, context))
+        })
+        .with_handler("metric", |action, memory, _context, _payload| {
+            Box::pin(async move {
+                let count = memory.get(&action.command).and_then(Value::as_i64);
+                memory.insert(action.command.clone(), (count.unwrap_or(0) + 1).into());
+                Ok(())
+            })
+        });
+    let state_machine =
+        StateMachine::new(json_config, None, handlers, Map::new(), Context {}).unwrap();
+    state_machine.trigger("start").await.unwrap();
+    let memory = state_machine.memory.read().await;
+    assert_eq!(
+        memory.get("log").unwrap(),
+        &serde_json::json!(["leaving Idle"])
+    );
+    assert_eq!(memory.get("runs").unwrap(), &serde_json::json!(1));
+    drop(memory);
+
+    // An action type without a handler is rejected when the machine is created
+    let handlers =
+        HandlerRegistry::new().with_handler("log", |action, memory, context, _payload| {
+            Box::pin(logging_action_handler(action, memory, context))
+        });
+    let err = StateMachine::new(json_config, None, handlers, Map::new(), Context {})
+        .err()
+        .unwrap();
+    assert_eq!(err, Error::MissingHandler("metric".to_string()));
+
+    // A handler for a built-in action type would never be called
+    let handlers = HandlerRegistry::new()
+        .with_fallback(|action, memory, context, _payload| {
+            Box::pin(logging_action_handler(action, memory, context))
+        })
+        .with_handler("increment", |_action, _memory, _context, _payload| {
+            Box::pin(async { Ok(()) })
+        });
+    let err = StateMachine::new(json_config, None, handlers, Map::new(), Context {})
+        .err()
+        .unwrap();
+    assert_eq!(err, Error::BuiltinHandler("increment".to_string()));
+
+    // ...unless a fallback handler covers it
+    let handlers = HandlerRegistry::new()
+        .with_handler("metric", |_action, _memory, _context, _payload| {
+            Box::pin(async { Err(ActionError::new("metrics are down")) })
+        })
+        .with_fallback(|action, memory, context, _payload| {
+            Box::pin(logging_action_handler(action, memory, context))
+        });
+    let state_machine =
+        StateMachine::new(json_config, None, handlers, Map::new(), Context {}).unwrap();
+    let err = state_machine.trigger("start").await.unwrap_err();
+    assert_eq!(err.code(), "action_failed");
+}
+
+/// Test action parameters and their per-type schemas.
+#[tokio::test]
+async fn test_action_params() {
+    let json_config = r#"
+    {
+        "action_schemas": {
+            "notify": {
+                "type": "object",
+                "required": ["channel"],
+                "properties": { "channel": { "type": "string" }, "retries": { "type": "integer" } }
+            }
+        },
+        "states": [
+            { "name": "Open" },
+            {
+                "name": "Closed",
+                "on_enter_actions": [
+                    {
+                        "action_type": "notify",
+                        "command": "ticket_closed",
+                        "params": { "channel": "support", "retries": 3 }
+                    }
+                ]
+            }
+        ],
+        "transitions": [
+            { "from": "Open", "event": "close", "to": "Closed" }
+        ]
+    }
+    "#;
+
+    let handlers = HandlerRegistry::new().with_handler(
+        "notify",
+        |action, memory, _context: &mut Context, _payload| {
+            Box::pin(async move {
+                memory.insert("notified".to_string(), action.params["channel"].clone());
+                Ok(())
+            })
+        },
+    );
+    let state_machine =
+        StateMachine::new(json_config, None, handlers, Map::new(), Context {}).unwrap();
+    state_machine.trigger("close").await.unwrap();
+    assert_eq!(
+        state_machine.memory.read().await.get("notified").unwrap(),
+        &serde_json::json!("support")
+    );
+
+    // Parameters that do not match the schema of their action type are rejected at load time
+    let invalid_config = json_config.replace(r#""channel": "support""#, r#""channel": 42"#);
+    let handlers = HandlerRegistry::new().with_fallback(|action, memory, context, _payload| {
+        Box::pin(logging_action_handler(action, memory, context))
+    });
+    let err = StateMachine::new(&invalid_config, None, handlers, Map::new(), Context {})
+        .err()
+        .unwrap();
+    assert_eq!(err.code(), "invalid_config");
+
+    // Templated parameters are checked once rendered, before the handler is called
+    let templated_config =
+        json_config.replace(r#""retries": 3"#, r#""retries": "{{memory.retries}}""#);
+    let new_machine = |retries: Value| {
+        let handlers = HandlerRegistry::new().with_fallback(|action, memory, context, _payload| {
+            Box::pin(logging_action_handler(action, memory, context))
+        });
+        let memory: Map<String, Value> =
+            serde_json::from_value(serde_json::json!({ "retries": retries })).unwrap();
+        StateMachine::new(&templated_config, None, handlers, memory, Context {}).unwrap()
+    };
+    new_machine(serde_json::json!(3))
+        .trigger("close")
+        .await
+        .unwrap();
+    let state_machine = new_machine(serde_json::json!("three"));
+    let err = state_machine.trigger("close").await.unwrap_err();
+    assert_eq!(err.code(), "template_failed");
+    assert_eq!(state_machine.get_current_state().await.unwrap(), "Open");
+}
+
+/// Test templates in action commands and params, in strict and lenient mode.
+#[tokio::test]
+async fn test_action_templates() {
+    let json_config = r#"
+    {
+        "states": [
+            { "name": "Draft" },
+            {
+                "name": "Sent",
+                "on_enter_actions": [
+                    {
+                        "action_type": "send",
+                        "command": "Send invoice {{memory.invoice_id}} to {{memory.customer.email}} ({{event.note}}) {{ref}}",
+                        "params": {
+                            "amount": "{{memory.amount}}",
+                            "lines": ["{{memory.customer.lines[0]}}"],
+                            "email": "{{memory/customer/email}}"
+                        }
+                    }
+                ]
+            }
+        ],
+        "transitions": [
+            { "from": "Draft", "event": "send", "to": "Sent" }
+        ]
+    }
+    "#;
+
+    let new_machine = |memory: Map<String, Value>| {
+        let handlers = HandlerRegistry::new().with_handler(
+            "send",
+            |action, memory, _context: &mut Context, _payload| {
+                Box::pin(async move {
+                    memory.insert("sent".to_string(), action.command.clone().into());
+                    memory.insert("params".to_string(), action.params.clone());
+                    Ok(())
+                })
+            },
+        );
+        StateMachine::new(json_config, None, handlers, memory, Context {}).unwrap()
+    };
+    let memory: Map<String, Value> = serde_json::from_value(serde_json::json!({
+        "invoice_id": 42,
+        "amount": 99.5,
+        "customer": { "email": "ada@example.com", "lines": ["widget"] }
+    }))
+    .unwrap();
+
+    let state_machine = new_machine(memory.clone());
+    state_machine
+        .trigger_with("send", serde_json::json!({ "note": "urgent" }))
+        .await
+        .unwrap();
+    let rendered = state_machine.memory.read().await.clone();
+    assert_eq!(
+        rendered.get("sent").unwrap(),
+        "Send invoice 42 to ada@example.com (urgent) {{ref}}"
+    );
+    assert_eq!(
+        rendered.get("params").unwrap(),
+        &serde_json::json!({
+            "amount": 99.5,
+            "lines": ["widget"],
+            "email": "ada@example.com"
+        })
+    );
+
+    // Strict mode fails the transition on a missing field
+    let state_machine = new_machine(memory.clone());
+    let err = state_machine.trigger("send").await.unwrap_err();
+    assert_eq!(err.code(), "template_failed");
+    assert_eq!(state_machine.get_current_state().await.unwrap(), "Draft");
+
+    // Lenient mode renders missing fields as empty
+    let state_machine = new_machine(memory.clone()).with_template_mode(TemplateMode::Lenient);
+    state_machine.trigger("send").await.unwrap();
+    assert_eq!(
+        state_machine.memory.read().await.get("sent").unwrap(),
+        "Send invoice 42 to ada@example.com () {{ref}}"
+    );
+
+    // Braces that do not name the memory or the event payload are literal text
+    let literal_config = json_config.replace("{{ref}}", "json {{ not a placeholder");
+    let state_machine = StateMachine::new(
+        &literal_config,
+        None,
+        HandlerRegistry::new().with_fallback(|action, memory, _context, _payload| {
+            Box::pin(async move {
+                memory.insert("sent".to_string(), action.command.clone().into());
+                Ok(())
+            })
+        }),
+        memory,
+        Context {},
+    )
+    .unwrap();
+    state_machine
+        .trigger_with("send", serde_json::json!({ "note": "urgent" }))
+        .await
+        .unwrap();
+    assert_eq!(
+        state_machine.memory.read().await.get("sent").unwrap(),
+        "Send invoice 42 to ada@example.com (urgent) json {{ not a placeholder"
+    );
+
+    // Malformed templates are reported when the configuration is loaded
+    for invalid in ["{{event.note", "{{memory..note}}", "{{memory.lines[*]}}"] {
+        let invalid_config = json_config.replace("{{ref}}", invalid);
+        let handlers = HandlerRegistry::new().with_fallback(|action, memory, context, _payload| {
+            Box::pin(logging_action_handler(action, memory, context))
+        });
+        let err = StateMachine::new(&invalid_config, None, handlers, Map::new(), Context {})
+            .err()
+            .unwrap();
+        assert_eq!(err.code(), "invalid_config");
+    }
+}
+
+/// Test built-in memory actions
+#[tokio::test]
+async fn test_builtin_actions() {
+    let json_config = r#"
+    {
+        "states": [
+            {
+                "name": "Idle",
+                "on_enter_actions": [],
+                "on_exit_actions": [],
+                "validations": []
+            },
+            {
+                "name": "Counting",
+                "on_enter_actions": [
+                    { "action_type": "increment", "command": "visits" },
+                    { "action_type": "increment", "command": "points", "params": "{{event.points}}" },
+                    { "action_type": "set", "command": "last_order", "params": "{{event.order_id}}" },
+                    { "action_type": "append", "command": "orders", "params": "{{event.order_id}}" },
+                    { "action_type": "merge", "command": "flags", "params": { "vip": true } },
+                    { "action_type": "copy", "command": "previous", "params": "visits" },
+                    { "action_type": "unset", "command": "draft" }
+                ],
+                "on_exit_actions": [],
+                "validations": []
+            }
+        ],
+        "transitions": [
+            { "from": "Idle", "event": "count", "to": "Counting" },
+            { "from": "Counting", "event": "reset", "to": "Idle" }
+        ]
+    }
+    "#;
+
+    // Built-in actions need no handler
+    let memory: Map<String, Value> = serde_json::from_value(serde_json::json!({
+        "points": 10,
+        "flags": { "new": false },
+        "draft": "pending"
+    }))
+    .unwrap();
+    let state_machine = StateMachine::new(
+        json_config,
+        None,
+        HandlerRegistry::new(),
+        memory,
+        Context {},
+    )
+    .unwrap();
+    for order_id in ["A1", "B2"] {
+        state_machine
+            .trigger_with(
+                "count",
+                serde_json::json!({ "points": 5, "order_id": order_id }),
+            )
+            .await
+            .unwrap();
+        state_machine.trigger("reset").await.unwrap();
+    }
+    assert_eq!(
+        Value::Object(state_machine.memory.read().await.clone()),
+        serde_json::json!({
+            "visits": 2,
+            "points": 20,
+            "last_order": "B2",
+            "orders": ["A1", "B2"],
+            "flags": { "new": false, "vip": true },
+            "previous": 2
+        })
+    );
+
+    // A built-in action on a field of the wrong type fails the transition
+    let mut memory = Map::new();
+    memory.insert("visits".to_string(), Value::from("many"));
+    let state_machine = StateMachine::new(
+        json_config,
+        None,
+        HandlerRegistry::new(),
+        memory,
+        Context {},
+    )
+    .unwrap();
+    let err = state_machine
+        .trigger_with(
+            "count",
+            serde_json::json!({ "points": 5, "order_id": "A1" }),
+        )
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), "action_failed");
+    assert_eq!(state_machine.get_current_state().await.unwrap(), "Idle");
+
+    // Params of the wrong shape are reported when the configuration is loaded
+    let invalid_config = json_config.replace(r#""params": "visits""#, r#""params": 3"#);
+    let err = StateMachine::new(
+        &invalid_config,
+        None,
+        HandlerRegistry::new(),
+        Map::new(),
+        Context {},
+    )
+    .err()
+    .unwrap();
+    assert_eq!(err.code(), "invalid_config");
+}
+
+/// Test read-only and non-editable fields
+#[tokio::test]
+async fn test_read_only_fields() {
+    let json_config = r#"
+    {
+        "states": [
+            {
+                "name": "Open",
+                "on_enter_actions": [],
+                "on_exit_actions": [],
+                "validations": []
+            },
+            {
+                "name": "Approved",
+                "on_enter_actions": [],
+                "on_exit_actions": [],
+                "validations": [
+                    { "field": "amount", "rules": [{ "type": "read_only", "is_read_only": true }] }
+                ]
+            },
+            {
+                "name": "Paid",
+                "on_enter_actions": [],
+                "on_exit_actions": [],
+                "validations": []
+            },
+            {
+                "name": "Failed",
+                "on_enter_actions": [],
+                "on_exit_actions": [],
+                "validations": []
+            }
+        ],
+        "transitions": [
+            {
+                "from": "Open",
+                "event": "approve",
+                "to": "Approved",
+                "actions": [{ "action_type": "set", "command": "amount", "params": "{{event.amount}}" }],
+                "validations": [
+                    { "field": "owner", "rules": [{ "type": "editable", "is_editable": false }] }
+                ]
+            },
+            {
+                "from": "Approved",
+                "event": "pay",
+                "to": "Paid",
+                "actions": [{ "action_type": "increment", "command": "{{event.field}}" }],
+                "error_policy": "redirect",
+                "on_error": "Failed"
+            }
+        ]
+    }
+    "#;
+
+    let new_machine = || {
+        let mut memory = Map::new();
+        memory.insert("owner".to_string(), Value::from("ada"));
+        memory.insert("payments".to_string(), Value::from(0));
+        StateMachine::new(
+            json_config,
+            None,
+            HandlerRegistry::new(),
+            memory,
+            Context {},
+        )
+        .unwrap()
+    };
+
+    // Fields outside the rules can be changed, and a state's read-only fields can be set on
+    // the way into it
+    let state_machine = new_machine();
+    state_machine
+        .trigger_with("approve", serde_json::json!({ "amount": 100 }))
+        .await
+        .unwrap();
+    state_machine
+        .trigger_with("pay", serde_json::json!({ "field": "payments" }))
+        .await
+        .unwrap();
+    assert_eq!(state_machine.get_current_state().await.unwrap(), "Paid");
+
+    // Changing a read-only field while in the state fails and rolls back, even with the
+    // redirect error policy
+    let state_machine = new_machine();
+    state_machine
+        .trigger_with("approve", serde_json::json!({ "amount": 100 }))
+        .await
+        .unwrap();
+    let err = state_machine
+        .trigger_with("pay", serde_json::json!({ "field": "amount" }))
+        .await
+        .unwrap_err();
+    match err {
+        Error::Validation(violation) => {
+            assert_eq!(violation.field, "amount");
+            assert_eq!(violation.rule, "read_only");
+        }
+        other => panic!("unexpected error: {:?}", other),
+    }
+    assert_eq!(state_machine.get_current_state().await.unwrap(), "Approved");
+    assert_eq!(
+        state_machine.memory.read().await.get("amount").unwrap(),
+        100
+    );
+
+    // An action failing after a read-only field was changed is not redirected either
+    let failing_config = json_config.replace(
+        r#""actions": [{ "action_type": "increment", "command": "{{event.field}}" }]"#,
+        r#""actions": [
+                    { "action_type": "increment", "command": "{{event.field}}" },
+                    { "action_type": "increment", "command": "owner" }
+                ]"#,
+    );
+    let new_failing_machine = || {
+        let mut memory = Map::new();
+        memory.insert("owner".to_string(), Value::from("ada"));
+        memory.insert("payments".to_string(), Value::from(0));
+        StateMachine::new(
+            &failing_config,
+            None,
+            HandlerRegistry::new(),
+            memory,
+            Context {},
+        )
+        .unwrap()
+    };
+    for (field, state, rule) in [
+        ("payments", "Failed", None),
+        ("amount", "Approved", Some("read_only")),
+    ] {
+        let state_machine = new_failing_machine();
+        state_machine
+            .trigger_with("approve", serde_json::json!({ "amount": 100 }))
+            .await
+            .unwrap();
+        let err = state_machine
+            .trigger_with("pay", serde_json::json!({ "field": field }))
+            .await
+            .unwrap_err();
+        match (err, rule) {
+            (Error::ActionFailed { .. }, None) => {}
+            (Error::Validation(violation), Some(rule)) => assert_eq!(violation.rule, rule),
+            (other, _) => panic!("unexpected error: {:?}", other),
+        }
+        assert_eq!(state_machine.get_current_state().await.unwrap(), state);
+        assert_eq!(
+            state_machine.memory.read().await.get("amount").unwrap(),
+            100
+        );
+    }
+
+    // Non-editable fields of a transition cannot be changed by its actions
+    let invalid_config = json_config.replace(
+        r#""command": "amount", "params": "{{event.amount}}""#,
+        r#""command": "owner", "params": "{{event.amount}}""#,
+    );
+    let mut memory = Map::new();
+    memory.insert("owner".to_string(), Value::from("ada"));
+    let state_machine = StateMachine::new(
+        &invalid_config,
+        None,
+        HandlerRegistry::new(),
+        memory,
+        Context {},
+    )
+    .unwrap();
+    let err = state_machine
+        .trigger_with("approve", serde_json::json!({ "amount": 100 }))
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), "validation_failed");
+    assert_eq!(state_machine.get_current_state().await.unwrap(), "Open");
+    assert_eq!(
+        state_machine.memory.read().await.get("owner").unwrap(),
+        "ada"
+    );
+
+    // In CollectAll mode, every changed field is reported
+    let collect_config = invalid_config
+        .replace(
+            r#"{ "action_type": "set", "command": "owner", "params": "{{event.amount}}" }"#,
+            r#"{ "action_type": "set", "command": "owner", "params": "{{event.amount}}" },
+                    { "action_type": "increment", "command": "payments" }"#,
+        )
+        .replace(
+            r#"{ "field": "owner", "rules": [{ "type": "editable", "is_editable": false }] }"#,
+            r#"{ "field": "owner", "rules": [{ "type": "editable", "is_editable": false }] },
+                    { "field": "payments", "rules": [{ "type": "editable", "is_editable": false }] }"#,
+        );
+    let state_machine = StateMachine::new(
+        &collect_config,
+        None,
+        HandlerRegistry::new(),
+        Map::new(),
+        Context {},
+    )
+    .unwrap()
+    .with_validation_mode(ValidationMode::CollectAll);
+    match state_machine
+        .trigger_with("approve", serde_json::json!({ "amount": 100 }))
+        .await
+        .unwrap_err()
+    {
+        Error::Validations(violations) => {
+            let fields: Vec<&str> = violations.iter().map(|v| v.field.as_str()).collect();
+            assert_eq!(fields, vec!["owner", "payments"]);
+            assert_eq!(violations[0].rule, "editable");
+            assert_eq!(
+                violations[0].message,
+                "is not editable but was changed by an action"
+            );
+        }
+        other => panic!("unexpected error: {:?}", other),
+    }
+    assert!(state_machine.memory.read().await.is_empty());
+}
+
+/// Test string, array and pattern validation rules
+#[tokio::test]
+async fn test_string_and_array_rules() {
+    let json_config = r#"
+    {
+        "states": [
+            {
+                "name": "Draft",
+                "on_enter_actions": [],
+                "on_exit_actions": [],
+                "validations": [
+                    {
+                        "field": "code",
+                        "rules": [
+                            { "type": "min_length", "value": 3 },
+                            { "type": "max_length", "value": 8 },
+                            { "type": "pattern", "pattern": "^[A-Z]+-[0-9]+$" },
+                            { "type": "contains", "value": "-" }
+                        ]
+                    },
+                    {
+                        "field": "email",
+                        "rules": [{ "type": "format", "format": "email" }]
+                    },
+                    {
+                        "field": "id",
+                        "rules": [{ "type": "format", "format": "uuid" }]
+                    },
+                    {
+                        "field": "created_at",
+                        "rules": [{ "type": "format", "format": "date-time" }]
+                    },
+                    {
+                        "field": "tags",
+                        "rules": [
+                            { "type": "min_length", "value": 1 },
+                            { "type": "contains", "value": "urgent" },
+                            { "type": "unique_items", "is_unique": true }
+                        ]
+                    }
+                ]
+            },
+            {
+                "name": "Submitted",
+                "on_enter_actions": [],
+                "on_exit_actions": [],
+                "validations": []
+            }
+        ],
+        "transitions": [
+            { "from": "Draft", "event": "submit", "to": "Submitted" }
+        ]
+    }
+    "#;
+
+    let valid = serde_json::json!({
+        "code": "INV-42",
+        "email": "ada@example.com",
+        "id": "67e55044-10b1-426f-9247-bb680e5fe0c8",
+        "created_at": "2024-05-01T12:00:00Z",
+        "tags": ["urgent", "billing"]
+    });
+    let submit = |overrides: Value| {
+        let mut memory = valid.as_object().unwrap().clone();
+        memory.extend(overrides.as_object().unwrap().clone());
+        async move {
+            let state_machine = StateMachine::new(
+                json_config,
+                None,
+                HandlerRegistry::new(),
+                memory,
+                Context {},
+            )
+            .unwrap();
+            match state_machine.trigger("submit").await {
+                Ok(()) => None,
+                Err(Error::Validation(violation)) => Some((violation.field, violation.rule)),
+                Err(other) => panic!("unexpected error: {:?}", other),
+            }
+        }
+    };
+    let violation = |field: &str, rule: &str| Some((field.to_string(), rule.to_string()));
+
+    assert_eq!(submit(serde_json::json!({})).await, None);
+    assert_eq!(
+        submit(serde_json::json!({ "code": "AB" })).await,
+        violation("code", "min_length")
+    );
+    assert_eq!(
+        submit(serde_json::json!({ "code": "INV-123456" })).await,
+        violation("code", "max_length")
+    );
+    assert_eq!(
+        submit(serde_json::json!({ "code": "inv-42" })).await,
+        violation("code", "pattern")
+    );
+    assert_eq!(
+        submit(serde_json::json!({ "email": "not an email" })).await,
+        violation("email", "format")
+    );
+    assert_eq!(
+        submit(serde_json::json!({ "id": "67e55044" })).await,
+        violation("id", "format")
+    );
+    assert_eq!(
+        submit(serde_json::json!({ "created_at": "yesterday" })).await,
+        violation("created_at", "format")
+    );
+    assert_eq!(
+        submit(serde_json::json!({ "tags": ["billing"] })).await,
+        violation("tags", "contains")
+    );
+    assert_eq!(
+        submit(serde_json::json!({ "tags": ["urgent", "urgent"] })).await,
+        violation("tags", "unique_items")
+    );
+
+    // Invalid patterns are reported when the configuration is loaded
+    let invalid_config = json_config.replace("^[A-Z]+-[0-9]+$", "[A-Z");
+    let err = StateMachine::new(
+        &invalid_config,
+        None,
+        HandlerRegistry::new(),
+        Map::new(),
+        Context {},
+    )
+    .err()
+    .unwrap();
+    assert_eq!(err.code(), "config_parse");
+}
+
+/// Test nested field paths, JSON Pointers and wildcards
+#[tokio::test]
+async fn test_field_paths() {
+    let json_config = r#"
+    {
+        "states": [
+            {
+                "name": "Cart",
+                "on_enter_actions": [],
+                "on_exit_actions": [],
+                "validations": [
+                    {
+                        "field": "order.customer.address.country",
+                        "rules": [{ "type": "enum", "values": ["FR", "DE"] }]
+                    },
+                    {
+                        "field": "/order/items/0/sku",
+                        "rules": [{ "type": "pattern", "pattern": "^SKU-" }]
+                    },
+                    {
+                        "field": "order.items[*].qty",
+                        "rules": [{ "type": "min_value", "value": 1 }]
+                    }
+                ]
+            },
+            {
+                "name": "Express",
+                "on_enter_actions": [
+                    { "action_type": "set", "command": "order.shipping.method", "params": "express" },
+                    { "action_type": "increment", "command": "order.items[0].qty" }
+                ],
+                "on_exit_actions": [],
+                "validations": []
+            },
+            {
+                "name": "Standard",
+                "on_enter_actions": [],
+                "on_exit_actions": [],
+                "validations": []
+            }
+        ],
+        "transitions": [
+            {
+                "from": "Cart",
+                "event": "checkout",
+                "to": "Express",
+                "guard": { "field": "order.customer.tier", "operator": "==", "value": "gold" }
+            },
+            { "from": "Cart", "event": "checkout", "to": "Standard" }
+        ]
+    }
+    "#;
+
+    let new_machine = |tier: &str, second_qty: i64| {
+        let memory: Map<String, Value> = serde_json::from_value(serde_json::json!({
+            "order": {
+                "customer": { "tier": tier, "address": { "country": "FR" } },
+                "items": [{ "sku": "SKU-1", "qty": 2 }, { "sku": "SKU-2", "qty": second_qty }]
+            }
+        }))
+        .unwrap();
+        StateMachine::new(
+            json_config,
+            None,
+            HandlerRegistry::new(),
+            memory,
+            Context {},
+        )
+        .unwrap()
+    };
+
+    // Guards read nested fields and built-in actions write them
+    let state_machine = new_machine("gold", 1);
+    state_machine.trigger("checkout").await.unwrap();
+    assert_eq!(state_machine.get_current_state().await.unwrap(), "Express");
+    let memory = state_machine.memory.read().await.clone();
+    assert_eq!(memory["order"]["shipping"]["method"], "express");
+    assert_eq!(memory["order"]["items"][0]["qty"], 3);
+
+    let state_machine = new_machine("silver", 1);
+    state_machine.trigger("checkout").await.unwrap();
+    assert_eq!(state_machine.get_current_state().await.unwrap(), "Standard");
+
+    // A wildcard rule reports the item that failed
+    let state_machine = new_machine("gold", 0);
+    match state_machine.trigger("checkout").await.unwrap_err() {
+        Error::Validation(violation) => {
+            assert_eq!(violation.field, "order.items[*].qty");
+            assert_eq!(violation.path, "order.items[1].qty");
+            assert_eq!(violation.rule, "min_value");
+        }
+        other => panic!("unexpected error: {:?}", other),
+    }
+
+    // Malformed paths are reported when the configuration is loaded
+    let invalid_config = json_config.replace("order.items[*].qty", "order.items[x].qty");
+    let err = StateMachine::new(
+        &invalid_config,
+        None,
+        HandlerRegistry::new(),
+        Map::new(),
+        Context {},
+    )
+    .err()
+    .unwrap();
+    assert_eq!(err.code(), "config_parse");
+
+    // A wildcard over a value that is not an array, or over a missing value, is checked like a
+    // missing field and reported on that value
+    for (items, reason) in [
+        (
+            serde_json::json!({ "0": { "sku": "SKU-1" } }),
+            "is of type 'object', not an array",
+        ),
+        (Value::Null, "is missing in memory"),
+    ] {
+        let mut order = serde_json::json!({
+            "customer": { "tier": "gold", "address": { "country": "FR" } }
+        });
+        if !items.is_null() {
+            order["items"] = items;
+        }
+        let mut memory = Map::new();
+        memory.insert("order".to_string(), order);
+        let state_machine = StateMachine::new(
+            json_config,
+            None,
+            HandlerRegistry::new(),
+            memory,
+            Context {},
+        )
+        .unwrap()
+        .with_validation_mode(ValidationMode::CollectAll);
+        let Err(Error::Validations(violations)) = state_machine.trigger("checkout").await else {
+            panic!("expected the items to be reported");
+        };
+        let violation = violations.last().unwrap();
+        assert_eq!(violation.path, "order.items");
+        assert_eq!(violation.rule, "min_value");
+        assert_eq!(violation.message, reason);
+    }
+
+    // A wildcard guard over a missing array behaves like a guard on a missing field
+    for (guard, result) in [
+        (r#""operator": ">", "value": 5"#, Err("condition_failed")),
+        (r#""operator": "exists""#, Ok("Standard")),
+    ] {
+        let guard_config = json_config.replace(
+            r#""field": "order.customer.tier", "operator": "==", "value": "gold""#,
+            &format!(r#""field": "order.lines[*].qty", {}"#, guard),
+        );
+        let memory: Map<String, Value> = serde_json::from_value(serde_json::json!({
+            "order": {
+                "customer": { "address": { "country": "FR" } },
+                "items": [{ "sku": "SKU-1", "qty": 2 }]
+            }
+        }))
+        .unwrap();
+        let state_machine = StateMachine::new(
+            &guard_config,
+            None,
+            HandlerRegistry::new(),
+            memory,
+            Context {},
+        )
+        .unwrap();
+        match (state_machine.trigger("checkout").await, result) {
+            (Ok(()), Ok(state)) => {
+                assert_eq!(state_machine.get_current_state().await.unwrap(), state);
+            }
+            (Err(err), Err(code)) => assert_eq!(err.code(), code),
+            (outcome, _) => panic!("unexpected result: {:?}", outcome),
+        }
+    }
+}
+
+/// Test compound all / any / not conditions
+#[tokio::test]
+async fn test_compound_conditions() {
+    let json_config = r#"
+    {
+        "states": [
+            {
+                "name": "Pricing",
+                "on_enter_actions": [],
+                "on_exit_actions": [],
+                "validations": [
+                    {
+                        "field": "discount",
+                        "rules": [{ "type": "max_value", "value": 10 }],
+                        "condition": {
+                            "all": [
+                                { "field": "tier", "operator": "==", "value": "gold" },
+                                { "not": { "field": "region", "operator": "==", "value": "EU" } }
+                            ]
+                        }
+                    }
+                ]
+            },
+            {
+                "name": "Priority",
+                "on_enter_actions": [],
+                "on_exit_actions": [],
+                "validations": []
+            },
+            {
+                "name": "Regular",
+                "on_enter_actions": [],
+                "on_exit_actions": [],
+                "validations": []
+            }
+        ],
+        "transitions": [
+            {
+                "from": "Pricing",
+                "event": "submit",
+                "to": "Priority",
+                "guard": {
+                    "any": [
+                        { "field": "tier", "operator": "==", "value": "gold" },
+                        { "field": "total", "operator": ">", "value": 1000 }
+                    ]
+                }
+            },
+            { "from": "Pricing", "event": "submit", "to": "Regular" }
+        ]
+    }
+    "#;
+
+    let submit = |memory: Value| async move {
+        let state_machine = StateMachine::new(
+            json_config,
+            None,
+            HandlerRegistry::new(),
+            memory.as_object().unwrap().clone(),
+            Context {},
+        )
+        .unwrap();
+        state_machine.trigger("submit").await?;
+        state_machine.get_current_state().await
+    };
+
+    // The validation only applies to gold customers outside the EU
+    let err = submit(serde_json::json!({
+        "tier": "gold", "region": "US", "discount": 20, "total": 10
+    }))
+    .await
+    .unwrap_err();
+    assert_eq!(err.code(), "validation_failed");
+    assert_eq!(
+        submit(serde_json::json!({ "tier": "gold", "region": "EU", "discount": 20, "total": 10 }))
+            .await
+            .unwrap(),
+        "Priority"
+    );
+    assert_eq!(
+        submit(
+            serde_json::json!({ "tier": "silver", "region": "US", "discount": 20, "total": 5000 })
+        )
+        .await
+        .unwrap(),
+        "Priority"
+    );
+    assert_eq!(
+        submit(
+            serde_json::json!({ "tier": "silver", "region": "US", "discount": 20, "total": 10 })
+        )
+        .await
+        .unwrap(),
+        "Regular"
+    );
+
+    // Malformed condition trees are reported when the configuration is loaded
+    for (valid, invalid) in [
+        (
+            r#"{ "not": { "field": "region", "operator": "==", "value": "EU" } }"#,
+            r#"{ "not": [] }"#,
+        ),
+        (
+            r#""operator": ">", "value": 1000"#,
+            r#""operator": ">", "value": 1000, "unit": "EUR""#,
+        ),
+    ] {
+        let invalid_config = json_config.replace(valid, invalid);
+        let err = StateMachine::new(
+            &invalid_config,
+            None,
+            HandlerRegistry::new(),
+            Map::new(),
+            Context {},
+        )
+        .err()
+        .unwrap();
+        assert_eq!(err.code(), "schema_violation");
+    }
+}
+
+/// Test the set, string and date-time condition operators
+#[tokio::test]
+async fn test_condition_operators() {
+    let json_config = r#"
+    {
+        "states": [
+            {
+                "name": "Idle",
+                "on_enter_actions": [],
+                "on_exit_actions": [],
+                "validations": []
+            },
+            {
+                "name": "Matched",
+                "on_enter_actions": [],
+                "on_exit_actions": [],
+                "validations": []
+            },
+            {
+                "name": "Unmatched",
+                "on_enter_actions": [],
+                "on_exit_actions": [],
+                "validations": []
+            }
+        ],
+        "transitions": [
+            {
+                "from": "Idle",
+                "event": "check",
+                "to": "Matched",
+                "guard": { "field": "event.field", "operator": "OPERATOR", "value": VALUE }
+            },
+            { "from": "Idle", "event": "check", "to": "Unmatched" }
+        ]
+    }
+    "#;
+
+    let check = |operator: &str, value: &str, field: Value| {
+        let config = json_config
+            .replace("OPERATOR", operator)
+            .replace("VALUE", value);
+        async move {
+            let state_machine = StateMachine::new(
+                &config,
+                None,
+                HandlerRegistry::new(),
+                Map::new(),
+                Context {},
+            )
+            .unwrap();
+            let payload = match field {
+                Value::Null => serde_json::json!({}),
+                field => serde_json::json!({ "field": field }),
+            };
+            state_machine.trigger_with("check", payload).await.unwrap();
+            state_machine.get_current_state().await.unwrap() == "Matched"
+        }
+    };
+
+    assert!(check("in", r#"["EU", "US"]"#, "EU".into()).await);
+    assert!(!check("in", r#"["EU", "US"]"#, "APAC".into()).await);
+    assert!(check("not_in", r#"["EU", "US"]"#, "APAC".into()).await);
+    assert!(check("contains", r#""vip""#, serde_json::json!(["vip", "new"])).await);
+    assert!(check("contains", r#""@example""#, "ada@example.com".into()).await);
+    assert!(check("exists", "null", "anything".into()).await);
+    assert!(!check("exists", "null", Value::Null).await);
+    assert!(check("missing", "null", Value::Null).await);
+    assert!(check("matches", r#""^INV-[0-9]+$""#, "INV-42".into()).await);
+    assert!(!check("matches", r#""^INV-[0-9]+$""#, "PO-42".into()).await);
+    assert!(check("starts_with", r#""INV-""#, "INV-42".into()).await);
+    assert!(check("ends_with", r#""-42""#, "INV-42".into()).await);
+
+    // Strings are ordered lexicographically, and RFC 3339 date-times chronologically
+    assert!(check(">", r#""apple""#, "banana".into()).await);
+    assert!(
+        check(
+            "<",
+            r#""2024-05-01T12:00:00Z""#,
+            "2024-05-01T13:30:00+02:00".into()
+        )
+        .await
+    );
+    assert!(
+        check(
+            ">=",
+            r#""2024-05-01T12:00:00Z""#,
+            "2024-05-01T14:00:00+02:00".into()
+        )
+        .await
+    );
+
+    // Unknown operators and invalid operands are reported when the configuration is loaded
+    for (operator, value, code) in [
+        ("equals", "1", "schema_violation"),
+        ("in", "1", "config_parse"),
+        ("matches", r#""[a-""#, "config_parse"),
+    ] {
+        let config = json_config
+            .replace("OPERATOR", operator)
+            .replace("VALUE", value);
+        let err = StateMachine::new(
+            &config,
+            None,
+            HandlerRegistry::new(),
+            Map::new(),
+            Context {},
+        )
+        .err()
+        .unwrap();
+        assert_eq!(err.code(), code);
+    }
+}
+
+/// Test cross-field comparisons with `$ref_field`
+#[tokio::test]
+async fn test_field_references() {
+    let json_config = r#"
+    {
+        "states": [
+            {
+                "name": "Packing",
+                "on_enter_actions": [],
+                "on_exit_actions": [],
+                "validations": [
+                    {
+                        "field": "shipped_qty",
+                        "rules": [{ "type": "max_value", "value": { "$ref_field": "ordered_qty" } }]
+                    }
+                ]
+            },
+            {
+                "name": "Shipped",
+                "on_enter_actions": [],
+                "on_exit_actions": [],
+                "validations": []
+            },
+            {
+                "name": "Rescheduled",
+                "on_enter_actions": [],
+                "on_exit_actions": [],
+                "validations": []
+            }
+        ],
+        "transitions": [
+            {
+                "from": "Packing",
+                "event": "ship",
+                "to": "Shipped",
+                "guard": {
+                    "field": "event.end_date",
+                    "operator": ">",
+                    "value": { "$ref_field": "start_date" }
+                }
+            },
+            { "from": "Packing", "event": "ship", "to": "Rescheduled" }
+        ]
+    }
+    "#;
+
+    let ship = |shipped_qty: i64, end_date: &str| {
+        let memory: Map<String, Value> = serde_json::from_value(serde_json::json!({
+            "ordered_qty": 5,
+            "shipped_qty": shipped_qty,
+            "start_date": "2024-05-01T00:00:00Z"
+        }))
+        .unwrap();
+        let payload = serde_json::json!({ "end_date": end_date });
+        async move {
+            let state_machine = StateMachine::new(
+                json_config,
+                None,
+                HandlerRegistry::new(),
+                memory,
+                Context {},
+            )
+            .unwrap();
+            state_machine.trigger_with("ship", payload).await?;
+            state_machine.get_current_state().await
+        }
+    };
+
+    assert_eq!(ship(5, "2024-05-03T00:00:00Z").await.unwrap(), "Shipped");
+    assert_eq!(
+        ship(5, "2024-04-30T00:00:00Z").await.unwrap(),
+        "Rescheduled"
+    );
+    match ship(6, "2024-05-03T00:00:00Z").await.unwrap_err() {
+        Error::Validation(violation) => {
+            assert_eq!(violation.rule, "max_value");
+            assert_eq!(violation.message, "value '6' is greater than maximum '5'");
+        }
+        other => panic!("unexpected error: {:?}", other),
+    }
+
+    // A reference to a missing field fails the evaluation
+    let mut memory = Map::new();
+    memory.insert("shipped_qty".to_string(), Value::from(1));
+    let state_machine = StateMachine::new(
+        json_config,
+        None,
+        HandlerRegistry::new(),
+        memory,
+        Context {},
+    )
+    .unwrap();
+    let payload = serde_json::json!({ "end_date": "2024-05-03T00:00:00Z" });
+    let err = state_machine
+        .trigger_with("ship", payload.clone())
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), "condition_failed");
+    state_machine
+        .memory
+        .write()
+        .await
+        .insert("start_date".to_string(), "2024-05-01T00:00:00Z".into());
+    let err = state_machine
+        .trigger_with("ship", payload)
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), "validation_failed");
+    assert_eq!(state_machine.get_current_state().await.unwrap(), "Packing");
+}
+
+/// Test expression guards and `expr` validation rules
+#[tokio::test]
+async fn test_expressions() {
+    let json_config = r#"
+    {
+        "states": [
+            {
+                "name": "Checkout",
+                "on_enter_actions": [],
+                "on_exit_actions": [],
+                "validations": [
+                    {
+                        "field": "total",
+                        "rules": [
+                            { "type": "expr", "expr": "$value == sum(items[*].price * items[*].qty)" }
+                        ]
+                    }
+                ]
+            },
+            {
+                "name": "Placed",
+                "on_enter_actions": [],
+                "on_exit_actions": [],
+                "validations": []
+            },
+            {
+                "name": "Review",
+                "on_enter_actions": [],
+                "on_exit_actions": [],
+                "validations": []
+            }
+        ],
+        "transitions": [
+            {
+                "from": "Checkout",
+                "event": "place",
+                "to": "Placed",
+                "guard": "GUARD"
+            },
+            { "from": "Checkout", "event": "place", "to": "Review" }
+        ]
+    }
+    "#;
+    let guard =
+        "count(items) > 0 && all(items[*].qty >= 1) && lower(event.channel) in ['web', 'app']";
+
+    let place = |total: f64, channel: &str| {
+        let config = json_config.replace("GUARD", guard);
+        let memory: Map<String, Value> = serde_json::from_value(serde_json::json!({
+            "total": total,
+            "items": [
+                { "sku": "A-1", "price": 2.5, "qty": 4 },
+                { "sku": "B-2", "price": 10, "qty": 1 }
+            ]
+        }))
+        .unwrap();
+        let payload = serde_json::json!({ "channel": channel });
+        async move {
+            let state_machine =
+                StateMachine::new(&config, None, HandlerRegistry::new(), memory, Context {})
+                    .unwrap();
+            state_machine.trigger_with("place", payload).await?;
+            state_machine.get_current_state().await
+        }
+    };
+
+    assert_eq!(place(20.0, "Web").await.unwrap(), "Placed");
+    assert_eq!(place(20.0, "phone").await.unwrap(), "Review");
+    match place(25.0, "web").await.unwrap_err() {
+        Error::Validation(violation) => {
+            assert_eq!(violation.rule, "expr");
+            assert_eq!(
+                violation.message,
+                "does not satisfy '$value == sum(items[*].price * items[*].qty)'"
+            );
+        }
+        other => panic!("unexpected error: {:?}", other),
+    }
+
+    // Syntax and type errors, and expressions nested or chained too deeply, are reported when
+    // the configuration is loaded
+    let chained = format!("{} > 0", vec!["total"; 20_000].join(" + "));
+    let nested = format!("{}true{}", "(".repeat(20_000), ")".repeat(20_000));
+    for invalid in [
+        "count(items > 0",
+        "average(items[*].qty) > 1",
+        "sum(items[*].qty)",
+        "'web' * 2 > 1",
+        "$value > 1",
+        &chained,
+        &nested,
+    ] {
+        let config = json_config.replace("GUARD", invalid);
+        let err = StateMachine::new(
+            &config,
+            None,
+            HandlerRegistry::new(),
+            Map::new(),
+            Context {},
+        )
+        .err()
+        .unwrap();
+        assert_eq!(err.code(), "config_parse", "{}", invalid);
+    }
+}
+
+/// Test reporting every broken validation rule at once
+#[tokio::test]
+async fn test_collect_all_validations() {
+    let json_config = r#"
+    {
+        "states": [
+            {
+                "name": "Draft",
+                "on_enter_actions": [],
+                "on_exit_actions": [],
+                "validations": [
+                    {
+                        "field": "email",
+                        "rules": [
+                            { "type": "type_check", "expected_type": "string" },
+                            { "type": "format", "format": "email" }
+                        ]
+                    },
+                    {
+                        "field": "items[*].qty",
+                        "rules": [{ "type": "min_value", "value": 1 }]
+                    }
+                ]
+            },
+            {
+                "name": "Submitted",
+                "on_enter_actions": [],
+                "on_exit_actions": [],
+                "validations": []
+            }
+        ],
+        "transitions": [
+            {
+                "from": "Draft",
+                "event": "submit",
+                "to": "Submitted",
+                "validations": [
+                    {
+                        "field": "name",
+                        "rules": [{ "type": "min_length", "value": 1 }]
+                    }
+                ]
+            }
+        ]
+    }
+    "#;
+
+    let memory: Map<String, Value> = serde_json::from_value(serde_json::json!({
+        "email": "not-an-email",
+        "name": "",
+        "items": [{ "qty": 0 }, { "qty": 2 }, { "qty": -1 }]
+    }))
+    .unwrap();
+
+    // By default the first broken rule fails the step
+    let state_machine = StateMachine::new(
+        json_config,
+        None,
+        HandlerRegistry::new(),
+        memory.clone(),
+        Context {},
+    )
+    .unwrap();
+    match state_machine.trigger("submit").await.unwrap_err() {
+        Error::Validation(violation) => assert_eq!(violation.rule, "format"),
+        other => panic!("unexpected error: {:?}", other),
+    }
+
+    // In aggregate mode every state and transition validation is reported
+    let state_machine = StateMachine::new(
+        json_config,
+        None,
+        HandlerRegistry::new(),
+        memory,
+        Context {},
+    )
+    .unwrap()
+    .with_validation_mode(ValidationMode::CollectAll);
+    let err = state_machine.trigger("submit").await.unwrap_err();
+    assert_eq!(err.code(), "validation_failed");
+    let Error::Validations(violations) = err else {
+        panic!("unexpected error: {:?}", err);
+    };
+    let found: Vec<(&str, &str)> = violations
+        .iter()
+        .map(|violation| (violation.path.as_str(), violation.rule.as_str()))
+        .collect();
+    assert_eq!(
+        found,
+        [
+            ("email", "format"),
+            ("items[0].qty", "min_value"),
+            ("items[2].qty", "min_value"),
+            ("name", "min_length"),
+        ]
+    );
+    assert_eq!(state_machine.get_current_state().await.unwrap(), "Draft");
+}
+
+/// Test that concurrent triggers are applied one step at a time
+#[tokio::test]
+async fn test_concurrent_triggers() {
+    let json_config = r#"
+    {
+        "states": [
+            { "name": "A" },
+            { "name": "B" },
+            { "name": "C" }
+        ],
+        "transitions": [
+            {
+                "from": "A",
+                "event": "toB",
+                "to": "B",
+                "actions": [{ "action_type": "slow", "command": "wait" }]
+            },
+            { "from": "A", "event": "toC", "to": "C" }
+        ]
+    }
+    "#;
+
+    let handlers =
+        HandlerRegistry::new().with_handler("slow", |_action, _memory, _context, _payload| {
+            Box::pin(async {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                Ok(())
+            })
+        });
+    let state_machine =
+        StateMachine::new(json_config, None, handlers, Map::new(), Context {}).unwrap();
+
+    // `toC` is triggered while `toB` is running, so it must see the machine in B
+    let (to_b, to_c) = tokio::join!(state_machine.trigger("toB"), async {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        state_machine.trigger("toC").await
+    });
+    to_b.unwrap();
+    assert_eq!(to_c.unwrap_err().code(), "no_transition");
+    assert_eq!(state_machine.get_current_state().await.unwrap(), "B");
+}